@@ -0,0 +1,25 @@
+//! challenge 1-4: finds the single-byte xor encrypted line of a file of hex lines
+//!
+//! cargo run --bin challenge_1_4 -- 4.txt
+use std::{env, fs, process};
+
+use cryptopals_challenges::xor::detect_single_byte;
+
+fn main() {
+    let Some(path) = env::args().nth(1) else {
+        eprintln!("usage: challenge_1_4 <file of hex lines>");
+        process::exit(2);
+    };
+
+    let detection = fs::read_to_string(&path).map_err(Into::into).and_then(|lines| detect_single_byte(&lines));
+    match detection {
+        Ok(detection) => {
+            println!("line {}, key {:#04x}, score {:.2}", detection.line + 1, detection.key, detection.score);
+            println!("{}", String::from_utf8_lossy(&detection.plaintext).trim_end());
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
@@ -1,5 +1,4 @@
 use base64::{prelude::BASE64_STANDARD, Engine};
-use hex;
 use crate::{Result};
 
 pub fn hex_to_base64(hex_string: &str) -> Result<String> {
@@ -26,6 +25,7 @@ pub fn fixed_xor(hex_1: &str, hex_2: &str) -> Result<String> {
 #[cfg(test)]
 mod tests {
     use crate::hex::{fixed_xor, hex_to_base64};
+    use crate::xor::break_single_byte;
     #[test]
     pub fn test_conversion() {
         let hex_string = "49276d206b696c6c696e6720796f757220627261696e206c696b65206120706f69736f6e6f7573206d757368726f6f6d";
@@ -67,6 +67,8 @@ mod tests {
     pub fn test_single_xor_cypher() {
         let hex_string = "1b37373331363f78151b7f2b783431333d78397828372d363c78373e783a393b3736";
 
-        println!("{}",String::from_utf8(hex::decode(hex_string).unwrap()).unwrap())
+        let best = &break_single_byte(&hex::decode(hex_string).unwrap())[0];
+        assert_eq!(best.0, b'X');
+        assert_eq!(best.1, b"Cooking MC's like a pound of bacon");
     }
 }
\ No newline at end of file
@@ -1,4 +1,5 @@
-mod hex;
+pub mod hex;
+pub mod xor;
 
 pub type Error = Box<dyn std::error::Error>;
 pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,198 @@
+use std::thread;
+
+use crate::Result;
+
+/// relative frequency of the letters a to z in english text, spaces included
+const ENGLISH_LETTERS: [f64; 26] = [
+    0.0651738, 0.0124248, 0.0217339, 0.0349835, 0.1041442, 0.0197881, 0.0158610,
+    0.0492888, 0.0558094, 0.0009033, 0.0050529, 0.0331490, 0.0202124, 0.0564513,
+    0.0596302, 0.0137645, 0.0008606, 0.0497563, 0.0515760, 0.0729357, 0.0225134,
+    0.0082903, 0.0171272, 0.0013692, 0.0145984, 0.0007836,
+];
+const ENGLISH_SPACE: f64 = 0.1918182;
+/// digits and punctuation, rare enough that text made of them scores badly
+const ENGLISH_OTHER: f64 = 0.02;
+
+/// rates how much a candidate plaintext looks like the expected language, lower is better
+pub trait Scorer {
+    fn score(&self, text: &[u8]) -> f64;
+}
+
+impl<F: Fn(&[u8]) -> f64> Scorer for F {
+    fn score(&self, text: &[u8]) -> f64 {
+        self(text)
+    }
+}
+
+/// chi-square of the letter, space and punctuation counts against english frequencies,
+/// plus a fixed penalty for every byte that would not show up in text
+pub struct English {
+    pub non_printable_penalty: f64,
+}
+
+impl Default for English {
+    fn default() -> Self {
+        English { non_printable_penalty: 100.0 }
+    }
+}
+
+impl Scorer for English {
+    fn score(&self, text: &[u8]) -> f64 {
+        if text.is_empty() {
+            return 0.0;
+        }
+
+        let mut letters = [0usize; 26];
+        let mut spaces = 0;
+        let mut others = 0;
+        let mut non_printable = 0;
+        for &byte in text {
+            match byte {
+                b'a'..=b'z' | b'A'..=b'Z' => letters[(byte.to_ascii_lowercase() - b'a') as usize] += 1,
+                b' ' | b'\n' | b'\r' | b'\t' => spaces += 1,
+                _ if byte.is_ascii_graphic() => others += 1,
+                _ => non_printable += 1,
+            }
+        }
+
+        let len = text.len() as f64;
+        let chi_square = |observed: usize, frequency: f64| {
+            let expected = frequency * len;
+            (observed as f64 - expected).powi(2) / expected
+        };
+        let letters: f64 = letters.iter().zip(ENGLISH_LETTERS).map(|(&observed, frequency)| chi_square(observed, frequency)).sum();
+
+        letters + chi_square(spaces, ENGLISH_SPACE) + chi_square(others, ENGLISH_OTHER) + self.non_printable_penalty * non_printable as f64
+    }
+}
+
+pub fn single_byte_xor(bytes: &[u8], key: u8) -> Vec<u8> {
+    bytes.iter().map(|byte| byte ^ key).collect()
+}
+
+/// every key with its plaintext and score, ranked with `scorer` from best to worst
+pub fn break_single_byte_with(cipher: &[u8], scorer: &impl Scorer) -> Vec<(u8, Vec<u8>, f64)> {
+    let mut candidates: Vec<_> = (0..=u8::MAX)
+        .map(|key| {
+            let plaintext = single_byte_xor(cipher, key);
+            let score = scorer.score(&plaintext);
+            (key, plaintext, score)
+        })
+        .collect();
+    candidates.sort_by(|a, b| a.2.total_cmp(&b.2));
+
+    candidates
+}
+
+/// every key with its plaintext and score, the most english looking first
+pub fn break_single_byte(cipher: &[u8]) -> Vec<(u8, Vec<u8>, f64)> {
+    break_single_byte_with(cipher, &English::default())
+}
+
+/// the line of a challenge 1-4 style file that decrypts best under a single-byte key
+#[derive(Debug)]
+pub struct Detection {
+    /// index of the line, blank lines included
+    pub line: usize,
+    pub key: u8,
+    pub plaintext: Vec<u8>,
+    pub score: f64,
+}
+
+/// breaks every hex line of `hex_lines` with `scorer`, spread over the available cores,
+/// and keeps the best candidate of them all
+pub fn detect_single_byte_with(hex_lines: &str, scorer: &(impl Scorer + Sync)) -> Result<Detection> {
+    let mut ciphers = Vec::new();
+    for (line, text) in hex_lines.lines().enumerate() {
+        if !text.trim().is_empty() {
+            ciphers.push((line, hex::decode(text.trim())?));
+        }
+    }
+    if ciphers.is_empty() {
+        return Err("no hex lines to detect single-byte xor in".into());
+    }
+
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let batch = ciphers.len().div_ceil(threads);
+    let best = thread::scope(|scope| {
+        let workers: Vec<_> = ciphers
+            .chunks(batch)
+            .map(|batch| {
+                scope.spawn(move || {
+                    batch
+                        .iter()
+                        .map(|(line, cipher)| {
+                            let (key, plaintext, score) = break_single_byte_with(cipher, scorer).swap_remove(0);
+                            Detection { line: *line, key, plaintext, score }
+                        })
+                        .min_by(|a, b| a.score.total_cmp(&b.score))
+                })
+            })
+            .collect();
+        workers.into_iter().filter_map(|worker| worker.join().unwrap()).min_by(|a, b| a.score.total_cmp(&b.score))
+    });
+
+    Ok(best.unwrap())
+}
+
+/// the line of `hex_lines` most likely to be english xored with a single byte
+pub fn detect_single_byte(hex_lines: &str) -> Result<Detection> {
+    detect_single_byte_with(hex_lines, &English::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::xor::{break_single_byte, break_single_byte_with, detect_single_byte, single_byte_xor, English, Scorer};
+
+    #[test]
+    pub fn test_break_single_byte() {
+        let cipher = single_byte_xor(b"Now that the party is jumping, with the bass kicked in", 0x2a);
+        let candidates = break_single_byte(&cipher);
+
+        assert_eq!(candidates.len(), 256);
+        assert_eq!(candidates[0].0, 0x2a);
+        assert_eq!(candidates[0].1, b"Now that the party is jumping, with the bass kicked in");
+        assert!(candidates.windows(2).all(|pair| pair[0].2 <= pair[1].2));
+    }
+
+    #[test]
+    pub fn test_english_scoring() {
+        let english = English::default();
+        let text = english.score(b"the quick brown fox jumps over the lazy dog");
+        let shouted = english.score(b"THE QUICK BROWN FOX JUMPS OVER THE LAZY DOG");
+        let binary = english.score(b"the quick brown fox\x00\x01 jumps over\xff the lazy dog");
+        let noise = english.score(b"xq#zj%v@kw!qz^jx&vq*");
+
+        assert_eq!(text, shouted);
+        assert!(text < binary);
+        assert!(text < noise);
+        assert_eq!(english.score(b""), 0.0);
+    }
+
+    #[test]
+    pub fn test_pluggable_scorer() {
+        let cipher = single_byte_xor(b"zzzz", 0x10);
+        let most_zs = |text: &[u8]| -(text.iter().filter(|&&byte| byte == b'z').count() as f64);
+
+        assert_eq!(most_zs.score(b"zz"), -2.0);
+        assert_eq!(break_single_byte_with(&cipher, &most_zs)[0].0, 0x10);
+    }
+
+    #[test]
+    pub fn test_detect_single_byte() {
+        let mut lines: Vec<_> = (0..60u8).map(|i| hex::encode((0..30u8).map(|j| i.wrapping_mul(97) ^ j.wrapping_mul(j).wrapping_mul(31)).collect::<Vec<u8>>())).collect();
+        lines[41] = hex::encode(single_byte_xor(b"Now that the party is jumping\n", 0x35));
+        lines.insert(10, String::new());
+        let detection = detect_single_byte(&lines.join("\n")).unwrap();
+
+        assert_eq!(detection.line, 42);
+        assert_eq!(detection.key, 0x35);
+        assert_eq!(detection.plaintext, b"Now that the party is jumping\n");
+    }
+
+    #[test]
+    pub fn test_detect_single_byte_invalid() {
+        assert!(detect_single_byte("").is_err());
+        assert!(detect_single_byte("1b37\nzz").is_err());
+    }
+}
@@ -0,0 +1,16 @@
+// generates the c header of the ffi module into OUT_DIR; the copy checked in
+// as include/pngme.h is compared with it by tests/ffi.rs
+fn main() {
+    let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    println!("cargo:rerun-if-changed=src/ffi.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+
+    let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir)).unwrap();
+    cbindgen::Builder::new()
+        .with_src(format!("{}/src/ffi.rs", crate_dir))
+        .with_config(config)
+        .generate()
+        .expect("failed to generate the c header")
+        .write_to_file(format!("{}/pngme.h", out_dir));
+}
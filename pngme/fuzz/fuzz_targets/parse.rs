@@ -0,0 +1,18 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pngme::limits::Limits;
+
+fuzz_target!(|data: &[u8]| {
+    let limits = Limits {
+        max_total_size: 1 << 20,
+        max_decompressed_size: 1 << 22,
+        ..Limits::default()
+    };
+    if let Ok(png) = pngme::parse_with_limits(data, &limits) {
+        for chunk in png.chunks() {
+            let _ = chunk.is_crc_valid();
+        }
+        let _ = png.to_png().as_bytes();
+    }
+});
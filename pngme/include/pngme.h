@@ -0,0 +1,118 @@
+#ifndef PNGME_H
+#define PNGME_H
+
+/* generated by build.rs with cbindgen, do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef enum PngmeStatus {
+  PNGME_STATUS_OK = 0,
+  PNGME_STATUS_NULL_POINTER = 1,
+  /**
+   * a chunk type that is not valid or not allowed to hold a message
+   */
+  PNGME_STATUS_INVALID_ARGUMENT = 2,
+  PNGME_STATUS_INVALID_PNG = 3,
+  PNGME_STATUS_NOT_FOUND = 4,
+  PNGME_STATUS_FAILED = 5,
+  PNGME_STATUS_PANIC = 6,
+} PngmeStatus;
+
+/**
+ * a parsed png, owned by the caller until passed to `pngme_free`
+ */
+typedef struct PngmePng PngmePng;
+
+typedef struct PngmeChunk {
+  /**
+   * nul terminated
+   */
+  char chunk_type[5];
+  uint32_t length;
+  uint32_t crc;
+} PngmeChunk;
+
+/**
+ * message of the last failed call on this thread, empty after a successful
+ * one; valid until the next call
+ */
+const char *pngme_last_error(void);
+
+/**
+ * parses `len` bytes at `data`, which are copied
+ *
+ * # Safety
+ * `data` must point to `len` readable bytes and `out` to writable memory
+ */
+enum PngmeStatus pngme_open(const uint8_t *data, size_t len, struct PngmePng **out);
+
+/**
+ * lists every chunk, the array must be released with `pngme_free_chunks`
+ *
+ * # Safety
+ * `png` must come from `pngme_open`, `chunks` and `count` must be writable
+ */
+enum PngmeStatus pngme_list_chunks(const struct PngmePng *png,
+                                   struct PngmeChunk **chunks,
+                                   size_t *count);
+
+/**
+ * hides `message` in a chunk of type `chunk_type`, or in a free private
+ * chunk type when it is null; the type used is written to `used_type` unless
+ * it is null
+ *
+ * # Safety
+ * `png` must come from `pngme_open`, `chunk_type` must be null or nul
+ * terminated, `message` must point to `len` bytes and `used_type` must be
+ * null or hold 5 bytes
+ */
+enum PngmeStatus pngme_encode(struct PngmePng *png,
+                              const char *chunk_type,
+                              const uint8_t *message,
+                              size_t len,
+                              char *used_type);
+
+/**
+ * decodes the message in the chunk of type `chunk_type`, or the one recorded
+ * by an earlier encode when it is null; release it with `pngme_free_bytes`
+ *
+ * # Safety
+ * `png` must come from `pngme_open`, `chunk_type` must be null or nul
+ * terminated, `message` and `len` must be writable
+ */
+enum PngmeStatus pngme_decode(const struct PngmePng *png,
+                              const char *chunk_type,
+                              uint8_t **message,
+                              size_t *len);
+
+/**
+ * serializes the png, release the bytes with `pngme_free_bytes`
+ *
+ * # Safety
+ * `png` must come from `pngme_open`, `data` and `len` must be writable
+ */
+enum PngmeStatus pngme_to_bytes(const struct PngmePng *png, uint8_t **data, size_t *len);
+
+/**
+ * # Safety
+ * `data` and `len` must come from `pngme_decode` or `pngme_to_bytes`
+ */
+void pngme_free_bytes(uint8_t *data, size_t len);
+
+/**
+ * # Safety
+ * `chunks` and `count` must come from `pngme_list_chunks`
+ */
+void pngme_free_chunks(struct PngmeChunk *chunks, size_t count);
+
+/**
+ * # Safety
+ * `png` must be null or come from `pngme_open`, it is invalid afterwards
+ */
+void pngme_free(struct PngmePng *png);
+
+#endif  /* PNGME_H */
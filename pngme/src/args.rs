@@ -1,21 +1,245 @@
-use clap::{Parser, Subcommand};
+use clap::{Args as ClapArgs, Parser, Subcommand};
+
+use pngme::commands::Mode;
+use pngme::image::ColorType;
+use pngme::payload::Compression;
+use pngme::reorder::Rule;
+use pngme::watermark::DEFAULT_STRENGTH;
+
+use crate::config::Strictness;
 
 #[derive(Parser)]
 #[command(version, about, long_about=None)]
 pub struct Args {
+    /// applies the options of the named profile from pngme.toml
+    #[arg(long, global = true)]
+    pub profile: Option<String>,
+    /// how strictly input files are checked before they are processed
+    #[arg(long, global = true, value_enum)]
+    pub strictness: Option<Strictness>,
+    /// records every write in a history chunk of the file, see `history`
+    #[arg(long, global = true)]
+    pub history: bool,
+    /// signs the history entries with the secret key in the given file
+    #[arg(long, global = true)]
+    pub history_key: Option<String>,
 
     #[command(subcommand)]
     pub command: Commands,
 }
 
+#[derive(ClapArgs)]
+pub struct Targets {
+    /// png files, directories or glob patterns to process
+    #[arg(required = true, num_args = 1..)]
+    pub paths: Vec<String>,
+    /// also processes png files in subdirectories
+    #[arg(short, long)]
+    pub recursive: bool,
+}
+
 #[derive(Subcommand)]
 pub enum Commands {
     /// encode a given message as a new chunk in the file specified
-    Encode { file_path: String, chunk_type: String, message: String, output: Option<String> },
+    Encode {
+        #[command(flatten)]
+        targets: Targets,
+        message: String,
+        #[arg(short, long, value_enum, default_value_t = Mode::Chunk)]
+        mode: Mode,
+        /// chunk type to store the message in, picks a free private type if omitted
+        #[arg(short = 't', long, conflicts_with = "mode")]
+        chunk_type: Option<String>,
+        /// output file, only allowed when encoding a single file
+        #[arg(short, long)]
+        output: Option<String>,
+        /// passphrase picking the pixels used by lsb mode
+        #[arg(long, required_if_eq("mode", "lsb"))]
+        passphrase: Option<String>,
+        /// reports where the message would go, with the lsb estimate, without writing
+        #[arg(long, conflicts_with = "output")]
+        dry_run: bool,
+        /// adds the given number of reed-solomon parity bytes to every 255 byte block
+        #[arg(long, value_parser = clap::value_parser!(u8).range(1..255))]
+        ecc: Option<u8>,
+        /// signs the message with the secret key in the given file
+        #[arg(long)]
+        sign: Option<String>,
+        /// compresses the message before storing it, decode detects it
+        #[arg(long, value_enum)]
+        compress: Option<Compression>,
+    },
     /// decodes the message in the given chunk for the specified file
-    Decode { file_path: String, chunk_type: String },
+    Decode {
+        #[command(flatten)]
+        targets: Targets,
+        #[arg(short, long, value_enum, default_value_t = Mode::Chunk)]
+        mode: Mode,
+        /// chunk type holding the message, defaults to the one picked by encode
+        #[arg(short = 't', long, conflicts_with = "mode")]
+        chunk_type: Option<String>,
+        #[arg(long, required_if_eq("mode", "lsb"))]
+        passphrase: Option<String>,
+        /// fails unless the message is signed by the public key in the given file
+        #[arg(long)]
+        verify: Option<String>,
+    },
     /// removes the given chunk from the specified file
-    Remove { file_path: String, chunk_type: String },
+    Remove {
+        #[command(flatten)]
+        targets: Targets,
+        chunk_type: String,
+    },
+    /// reports how many bytes each mode can hide
+    Capacity {
+        #[command(flatten)]
+        targets: Targets,
+    },
     /// prints all chunks
-    Print { file_path: String }
+    Print {
+        #[command(flatten)]
+        targets: Targets,
+    },
+    /// creates a new png, optionally encoding a message in it
+    Create {
+        output: String,
+        #[arg(long, default_value_t = 64)]
+        width: u32,
+        #[arg(long, default_value_t = 64)]
+        height: u32,
+        #[arg(long, value_enum, default_value_t = ColorType::Rgba)]
+        color_type: ColorType,
+        /// fills the image with a color given as RRGGBB or RRGGBBAA
+        #[arg(long, conflicts_with_all = ["noise", "raw"])]
+        color: Option<String>,
+        /// fills the image with random pixels
+        #[arg(long, conflicts_with = "raw")]
+        noise: bool,
+        /// seed for --noise, the current time if omitted
+        #[arg(long, requires = "noise")]
+        seed: Option<u64>,
+        /// reads 8 bit rgba pixels from the given file
+        #[arg(long)]
+        raw: Option<String>,
+        /// message to encode in the new png
+        #[arg(short, long)]
+        message: Option<String>,
+        #[arg(short = 't', long, requires = "message")]
+        chunk_type: Option<String>,
+        #[arg(long, requires = "message", value_parser = clap::value_parser!(u8).range(1..255))]
+        ecc: Option<u8>,
+        #[arg(long, requires = "message")]
+        sign: Option<String>,
+        #[arg(long, value_enum, requires = "message")]
+        compress: Option<Compression>,
+    },
+    /// moves chunks around following a rule or an explicit list of chunk types
+    Reorder {
+        #[command(flatten)]
+        targets: Targets,
+        #[arg(long, value_enum, required_unless_present = "order", conflicts_with = "order")]
+        rule: Option<Rule>,
+        /// comma separated chunk types, in the order they should appear
+        #[arg(long, value_delimiter = ',')]
+        order: Vec<String>,
+    },
+    /// changes the type of a chunk, e.g. to flip its private or safe-to-copy bit
+    RenameType {
+        old: String,
+        new: String,
+        #[command(flatten)]
+        targets: Targets,
+    },
+    /// recompresses the image data to win back bytes, keeping every safe-to-copy chunk
+    Optimize {
+        #[command(flatten)]
+        targets: Targets,
+        /// zlib compression level
+        #[arg(short, long, default_value_t = 9, value_parser = clap::value_parser!(u32).range(0..=9))]
+        level: u32,
+        /// splits the image data into IDAT chunks of at most this many bytes
+        #[arg(long)]
+        idat_size: Option<usize>,
+        /// drops ancillary chunks that are not safe to copy, except those affecting colors
+        #[arg(long)]
+        strip: bool,
+        /// comma separated ancillary chunk types for --strip to keep
+        #[arg(long, value_delimiter = ',', requires = "strip")]
+        keep: Vec<String>,
+        /// output file, only allowed when optimizing a single file
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// draws a preview of the image in the terminal
+    View {
+        #[command(flatten)]
+        targets: Targets,
+        /// width of the preview in characters, defaults to $COLUMNS or 80
+        #[arg(short, long)]
+        columns: Option<u32>,
+        /// shows the least significant bit of every channel instead of the image
+        #[arg(long, conflicts_with = "diff")]
+        lsb: bool,
+        /// highlights the pixels that differ from the given original image
+        #[arg(long)]
+        diff: Option<String>,
+    },
+    /// generates a key pair for signing messages as <PREFIX>.key and <PREFIX>.pub
+    Keygen {
+        prefix: String,
+    },
+    /// shows the recorded writes of each file and checks that they form an unbroken chain
+    History {
+        #[command(flatten)]
+        targets: Targets,
+        /// fails unless every entry is signed by the public key in the given file
+        #[arg(long)]
+        verify: Option<String>,
+    },
+    /// opens an interactive editor that keeps the file in memory until `save`
+    Shell {
+        path: String,
+    },
+    /// marks the pixels with an id that survives re-saving, small crops and requantisation
+    Watermark {
+        #[command(subcommand)]
+        action: WatermarkAction,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum WatermarkAction {
+    /// adds an invisible mark carrying the given id
+    Embed {
+        id: u32,
+        #[command(flatten)]
+        targets: Targets,
+        /// key the mark is made with, detect needs the same one
+        #[arg(long, default_value = "")]
+        key: String,
+        /// standard deviation of the mark in 8 bit levels, stronger marks survive more
+        #[arg(long, default_value_t = DEFAULT_STRENGTH)]
+        strength: f32,
+        /// output file, only allowed when marking a single file
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// reads the id of a mark and how confident the match is
+    Detect {
+        #[command(flatten)]
+        targets: Targets,
+        #[arg(long, default_value = "")]
+        key: String,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli_definition() {
+        Args::command().debug_assert();
+    }
 }
@@ -0,0 +1,148 @@
+//! tokio based counterparts of the file commands, enabled by the `async` feature
+
+use ed25519_dalek::VerifyingKey;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::carrier::{Carrier, Format};
+use crate::chunk::ChunkRef;
+use crate::limits::Limits;
+use crate::png::{Png, PngError};
+use crate::{commands, payload, Result};
+
+/// fills `buf` unless the stream ends first, returns how many bytes were read
+async fn read_full(reader: &mut (impl AsyncRead + Unpin), buf: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]).await? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+impl Png {
+    /// reads a png chunk by chunk within the default `Limits`, only awaiting
+    /// the stream; like `Png::try_from` crcs are not checked
+    pub async fn read_async(reader: impl AsyncRead + Unpin) -> Result<Png> {
+        Png::read_async_with_limits(reader, &Limits::default()).await
+    }
+
+    /// [`Png::read_async`] for untrusted uploads, within the given limits
+    pub async fn read_async_with_limits(mut reader: impl AsyncRead + Unpin, limits: &Limits) -> Result<Png> {
+        let mut signature = [0; 8];
+        if read_full(&mut reader, &mut signature).await? < signature.len() || signature != Png::STANDARD_HEADER {
+            return Err(Box::new(PngError { msg: "invalid png signature" }));
+        }
+
+        let (mut chunks, mut total) = (Vec::new(), signature.len());
+        loop {
+            let mut header = [0; 8];
+            match read_full(&mut reader, &mut header).await? {
+                0 => break,
+                8 => {}
+                _ => return Err(Box::new(PngError { msg: "truncated chunk" })),
+            }
+            let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+            limits.check_chunk(chunks.len(), length)?;
+            total += 12 + length as usize;
+            limits.check_total_size(total)?;
+
+            // the buffer only grows with bytes that arrive, a lying length cannot exhaust memory
+            let mut bytes = header.to_vec();
+            if (&mut reader).take(length as u64 + 4).read_to_end(&mut bytes).await? < length as usize + 4 {
+                return Err(Box::new(PngError { msg: "truncated chunk" }));
+            }
+            chunks.push(ChunkRef::parse(&bytes)?.0.to_chunk());
+        }
+
+        Ok(Png::from_chunks(chunks))
+    }
+
+    pub async fn write_async(&self, mut writer: impl AsyncWrite + Unpin) -> Result<()> {
+        writer.write_all(&self.as_bytes()).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+}
+
+/// reads a png from `reader`, hides `msg` in it like `commands::encode` in
+/// chunk mode and writes the result to `writer`; returns the chunk type used
+pub async fn encode_async(
+    reader: impl AsyncRead + Unpin,
+    writer: impl AsyncWrite + Unpin,
+    chunk_type: Option<String>,
+    msg: &[u8],
+    options: &payload::Options,
+) -> Result<String> {
+    let mut png = Png::read_async(reader).await?;
+    let tag = commands::encode_into(&mut png, chunk_type, msg, options)?;
+    png.write_async(writer).await?;
+
+    Ok(tag)
+}
+
+/// reads a png from `reader` and decodes the message like `commands::decode`
+/// in chunk mode, checking its signature against `verify` when given
+pub async fn decode_async(
+    reader: impl AsyncRead + Unpin,
+    chunk_type: Option<String>,
+    verify: Option<&VerifyingKey>,
+) -> Result<payload::Payload> {
+    let png = Png::read_async(reader).await?;
+    let (payload, location) = commands::find_payload(Format::Png, &png.segments(), chunk_type)?;
+    if let Some(key) = verify {
+        payload.verify(key, &location)?;
+    }
+
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::{ColorType, Image};
+
+    fn sample() -> Png {
+        Png::from_image(&Image::solid(4, 4, [1, 2, 3, 255], ColorType::Rgba).unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_read_write_duplex() {
+        let (client, server) = tokio::io::duplex(64);
+        let png = sample();
+        let (written, read) = tokio::join!(png.write_async(client), Png::read_async(server));
+        written.unwrap();
+        assert_eq!(read.unwrap().as_bytes(), png.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_encode_decode_async() {
+        let mut encoded = Vec::new();
+        let input = sample().as_bytes();
+        let tag = encode_async(&input[..], &mut encoded, None, b"async", &payload::Options::default()).await.unwrap();
+        assert_eq!(tag, "pmAa");
+
+        // spawned like a request handler, which needs the future to be Send
+        let decoding = tokio::spawn(decode_async(std::io::Cursor::new(encoded), None, None));
+        assert_eq!(decoding.await.unwrap().unwrap().message, b"async");
+    }
+
+    #[tokio::test]
+    async fn test_rejects_bad_input() {
+        let bytes = sample().as_bytes();
+        assert!(Png::read_async(&bytes[..bytes.len() - 3]).await.is_err());
+        assert!(Png::read_async(&b"not a png"[..]).await.is_err());
+
+        let limits = Limits { max_chunk_size: 8, ..Limits::default() };
+        assert!(Png::read_async_with_limits(&bytes[..], &limits).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_short_stream_with_huge_length() {
+        let mut bytes = Png::STANDARD_HEADER.to_vec();
+        bytes.extend_from_slice(&[0x3f, 0xff, 0xff, 0x00, b'I', b'D', b'A', b'T', 1, 2, 3, 4]);
+        let err = Png::read_async(&bytes[..]).await.unwrap_err();
+        assert_eq!(err.to_string(), "error while processing png: truncated chunk");
+    }
+}
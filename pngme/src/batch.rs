@@ -0,0 +1,159 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rayon::prelude::*;
+
+use crate::Result;
+
+/// extensions of the formats a `carrier` can be parsed from
+const EXTENSIONS: [&str; 5] = ["png", "wav", "webp", "jpg", "jpeg"];
+
+fn is_supported(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| EXTENSIONS.iter().any(|supported| ext.eq_ignore_ascii_case(supported)))
+}
+
+fn collect_dir(dir: &Path, recursive: bool, files: &mut Vec<PathBuf>) -> Result<()> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<std::io::Result<_>>()?;
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            if recursive {
+                collect_dir(&path, recursive, files)?;
+            }
+        } else if is_supported(&path) {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// expands glob patterns and directories into the list of files to process,
+/// directories contribute the supported files they contain; every file is
+/// listed once, where it was first reached
+pub fn expand(patterns: &[String], recursive: bool) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for pattern in patterns {
+        let mut matches: Vec<PathBuf> = glob::glob(pattern)?.collect::<std::result::Result<_, _>>()?;
+        if matches.is_empty() {
+            // let the command itself report missing files
+            matches.push(PathBuf::from(pattern));
+        }
+
+        for path in matches {
+            if path.is_dir() {
+                collect_dir(&path, recursive, &mut files)?;
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    // the same file reached twice would be written by two workers at once
+    let mut seen = HashSet::new();
+    files.retain(|path| seen.insert(fs::canonicalize(path).unwrap_or_else(|_| path.clone())));
+
+    Ok(files)
+}
+
+/// runs `f` over every file in parallel, results keep the order of `files`
+pub fn run<T, F>(files: &[PathBuf], f: F) -> Vec<(PathBuf, std::result::Result<T, String>)>
+where
+    T: Send,
+    F: Fn(&Path) -> Result<T> + Sync,
+{
+    files
+        .par_iter()
+        .map(|path| (path.clone(), f(path).map_err(|e| e.to_string())))
+        .collect()
+}
+
+/// prints a line per file and a summary, returns whether every file succeeded
+pub fn report<T: std::fmt::Display>(results: &[(PathBuf, std::result::Result<T, String>)]) -> bool {
+    let failed = results.iter().filter(|(_, res)| res.is_err()).count();
+    if let [(_, res)] = results {
+        match res {
+            Ok(out) => println!("{}", out),
+            Err(e) => eprintln!("error: {}", e),
+        }
+        return failed == 0;
+    }
+
+    for (path, res) in results {
+        match res {
+            Ok(out) => println!("{}: {}", path.display(), out),
+            Err(e) => eprintln!("{}: error: {}", path.display(), e),
+        }
+    }
+    println!("{} files processed, {} failed", results.len(), failed);
+
+    failed == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_tree(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("pngme-batch-{}-{}", name, std::process::id()));
+        fs::create_dir_all(root.join("nested")).unwrap();
+        for file in ["a.png", "b.PNG", "notes.txt", "nested/c.png"] {
+            fs::write(root.join(file), b"").unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn test_expand_directory() {
+        let root = temp_tree("dir");
+        let dir = root.to_string_lossy().into_owned();
+
+        assert_eq!(expand(std::slice::from_ref(&dir), false).unwrap().len(), 2);
+        assert_eq!(expand(&[dir], true).unwrap().len(), 3);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_expand_glob() {
+        let root = temp_tree("glob");
+        let pattern = root.join("*.png").to_string_lossy().into_owned();
+
+        let files = expand(&[pattern], false).unwrap();
+        assert_eq!(files, vec![root.join("a.png")]);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_expand_duplicates() {
+        let root = temp_tree("dup");
+        let a = root.join("a.png").to_string_lossy().into_owned();
+        let dir = root.to_string_lossy().into_owned();
+        let through_nested = root.join("nested/../a.png").to_string_lossy().into_owned();
+
+        let files = expand(&[a.clone(), dir, a, through_nested], false).unwrap();
+        assert_eq!(files, vec![root.join("a.png"), root.join("b.PNG")]);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_run_keeps_order_and_errors() {
+        let files: Vec<PathBuf> = (0..20).map(|i| PathBuf::from(i.to_string())).collect();
+        let results = run(&files, |path| {
+            let n: u32 = path.to_string_lossy().parse()?;
+            if n.is_multiple_of(2) {
+                Ok(n)
+            } else {
+                Err("odd".into())
+            }
+        });
+
+        assert_eq!(results.len(), 20);
+        assert_eq!(results[4].1, Ok(4));
+        assert_eq!(results[5].1, Err(String::from("odd")));
+        assert!(!report(&results));
+    }
+}
@@ -0,0 +1,229 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::jpeg::Jpeg;
+use crate::png::{Png, PngRef};
+use crate::riff::Riff;
+use crate::{Error, Result};
+
+#[derive(Debug)]
+pub struct CarrierError {
+    pub msg: &'static str,
+}
+
+impl fmt::Display for CarrierError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "error while processing file: {}", self.msg)
+    }
+}
+
+impl std::error::Error for CarrierError {}
+
+pub(crate) fn carrier_error(msg: &'static str) -> Error {
+    Box::new(CarrierError { msg })
+}
+
+/// container formats pngme can hide messages in, told apart by their magic bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Png,
+    /// WAV, WebP and other RIFF files
+    Riff,
+    Jpeg,
+}
+
+impl Format {
+    pub fn detect(bytes: &[u8]) -> Result<Format> {
+        if bytes.starts_with(&Png::STANDARD_HEADER) {
+            Ok(Format::Png)
+        } else if bytes.starts_with(b"RIFF") {
+            Ok(Format::Riff)
+        } else if bytes.starts_with(&[0xff, 0xd8]) {
+            Ok(Format::Jpeg)
+        } else {
+            Err(carrier_error("unknown file format"))
+        }
+    }
+
+    /// segment type of the note recording which segment `encode` picked
+    pub fn record_tag(self) -> &'static str {
+        match self {
+            Format::Png => "tEXt",
+            Format::Riff => "pmTY",
+            Format::Jpeg => "COM",
+        }
+    }
+}
+
+/// a chunk or segment of a carrier, `tag` is its type as shown to users
+#[derive(Debug, PartialEq, Eq)]
+pub struct Segment<'a> {
+    pub tag: String,
+    pub data: &'a [u8],
+}
+
+impl fmt::Display for Segment<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Length {} Type {}", self.data.len(), self.tag)
+    }
+}
+
+/// a file made of typed segments that can hold extra data
+pub trait Carrier: fmt::Display {
+    fn format(&self) -> Format;
+
+    fn segments(&self) -> Vec<Segment<'_>>;
+
+    /// fails when `tag` cannot hold a message without breaking the file
+    fn check_tag(&self, tag: &str) -> Result<()>;
+
+    /// a tag that is safe to store a message in and unused so far
+    fn free_tag(&self) -> Option<String>;
+
+    fn insert(&mut self, tag: &str, data: Vec<u8>) -> Result<()>;
+
+    /// removes the first segment with the given tag and returns its data
+    fn remove(&mut self, tag: &str) -> Result<Vec<u8>>;
+
+    fn retain(&mut self, f: &mut dyn FnMut(&Segment) -> bool);
+
+    fn as_bytes(&self) -> Vec<u8>;
+
+    /// what a signature on a message stored in `tag` covers besides the
+    /// message, taken from the file without that segment; the tag by default
+    fn signed_location(&self, tag: &str) -> Vec<u8> {
+        tag.as_bytes().to_vec()
+    }
+}
+
+/// parses any supported format into an editable carrier
+pub fn parse(bytes: &[u8]) -> Result<Box<dyn Carrier>> {
+    Ok(match Format::detect(bytes)? {
+        Format::Png => Box::new(Png::try_from(bytes)?),
+        Format::Riff => Box::new(Riff::try_from(bytes)?),
+        Format::Jpeg => Box::new(Jpeg::try_from(bytes)?),
+    })
+}
+
+/// lists the segments of any supported format without copying their data
+pub fn segments(bytes: &[u8]) -> Result<(Format, Vec<Segment<'_>>)> {
+    let format = Format::detect(bytes)?;
+    let segments = match format {
+        Format::Png => PngRef::try_from(bytes)?
+            .chunks()
+            .iter()
+            .map(|ch| Segment { tag: ch.chunk_type().to_string(), data: ch.data() })
+            .collect(),
+        Format::Riff => crate::riff::parse_chunks(bytes)?
+            .1
+            .into_iter()
+            .map(|(id, data)| Segment { tag: crate::riff::tag(&id), data })
+            .collect(),
+        Format::Jpeg => crate::jpeg::parse_segments(bytes)?
+            .0
+            .into_iter()
+            .map(|(marker, data)| Segment { tag: crate::jpeg::tag(marker), data })
+            .collect(),
+    };
+
+    Ok((format, segments))
+}
+
+fn chunk_segment(chunk: &Chunk) -> Segment<'_> {
+    Segment { tag: chunk.chunk_type().to_string(), data: chunk.data() }
+}
+
+impl Carrier for Png {
+    fn format(&self) -> Format {
+        Format::Png
+    }
+
+    fn segments(&self) -> Vec<Segment<'_>> {
+        self.chunks().iter().map(chunk_segment).collect()
+    }
+
+    fn check_tag(&self, tag: &str) -> Result<()> {
+        let chunk_type = ChunkType::from_str(tag)?;
+        if !chunk_type.is_valid() {
+            return Err(carrier_error("chunk type has the reserved bit set"));
+        }
+        if chunk_type.is_critical() {
+            return Err(carrier_error("critical chunk types make viewers reject the image"));
+        }
+
+        Ok(())
+    }
+
+    fn free_tag(&self) -> Option<String> {
+        self.unused_private_type().map(|ct| ct.to_string())
+    }
+
+    fn insert(&mut self, tag: &str, data: Vec<u8>) -> Result<()> {
+        self.insert_before_end(Chunk::new(ChunkType::from_str(tag)?, data));
+        Ok(())
+    }
+
+    fn remove(&mut self, tag: &str) -> Result<Vec<u8>> {
+        Ok(self.remove_chunk(tag)?.data().to_vec())
+    }
+
+    fn retain(&mut self, f: &mut dyn FnMut(&Segment) -> bool) {
+        self.retain_chunks(|ch| f(&chunk_segment(ch)));
+    }
+
+    fn as_bytes(&self) -> Vec<u8> {
+        Png::as_bytes(self)
+    }
+}
+
+/// first tag of the form `pm[A-Z][a-z]` for which `used` is false
+pub(crate) fn unused_tag(used: impl Fn(&str) -> bool) -> Option<String> {
+    (b'A'..=b'Z')
+        .flat_map(|third| (b'a'..=b'z').map(move |fourth| [b'p', b'm', third, fourth]))
+        .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+        .find(|tag| !used(tag))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Format::detect(&Png::STANDARD_HEADER).unwrap(), Format::Png);
+        assert_eq!(Format::detect(b"RIFF\0\0\0\0WAVE").unwrap(), Format::Riff);
+        assert_eq!(Format::detect(&[0xff, 0xd8, 0xff, 0xd9]).unwrap(), Format::Jpeg);
+        assert!(Format::detect(b"GIF89a").is_err());
+    }
+
+    #[test]
+    fn test_png_carrier() {
+        let mut png: Box<dyn Carrier> = Box::new(Png::from_chunks(Vec::new()));
+        let tag = png.free_tag().unwrap();
+        png.insert(&tag, b"hi".to_vec()).unwrap();
+        assert!(png.check_tag("IHDR").is_err());
+
+        let mut reparsed = parse(&png.as_bytes()).unwrap();
+        assert_eq!(reparsed.segments(), vec![Segment { tag: tag.clone(), data: b"hi" }]);
+        assert_eq!(reparsed.remove(&tag).unwrap(), b"hi");
+        assert!(reparsed.segments().is_empty());
+    }
+
+    #[test]
+    fn test_png_segments_go_before_iend() {
+        let image = crate::image::Image::solid(2, 2, [0, 0, 0, 255], crate::image::ColorType::Gray).unwrap();
+        let mut png = Png::from_image(&image).unwrap();
+        png.insert("ruSt", b"hi".to_vec()).unwrap();
+        png.insert("tEXt", b"note".to_vec()).unwrap();
+
+        let types: Vec<String> = png.chunks().iter().map(|ch| ch.chunk_type().to_string()).collect();
+        assert_eq!(types, ["IHDR", "IDAT", "ruSt", "tEXt", "IEND"]);
+    }
+
+    #[test]
+    fn test_unused_tag() {
+        assert_eq!(unused_tag(|tag| tag == "pmAa").unwrap(), "pmAb");
+    }
+}
@@ -1,12 +1,13 @@
 #![allow(unused_variables, dead_code)]
 use std::fmt;
 
+use crate::limits::MAX_CHUNK_SIZE;
 use crate::{chunk_type::ChunkType, Error, Result};
 use crc::{Crc, CRC_32_ISO_HDLC};
 
 const PNG32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub struct Chunk {
     chunk_type: ChunkType,
     length: u32,
@@ -15,7 +16,7 @@ pub struct Chunk {
 }
 
 #[derive(Debug)]
-struct ChunkError;
+pub(crate) struct ChunkError;
 
 impl fmt::Display for ChunkError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -25,44 +26,94 @@ impl fmt::Display for ChunkError {
 
 impl std::error::Error for ChunkError {}
 
-impl TryFrom<&[u8]> for Chunk {
-    type Error = Error;
+/// a chunk borrowing its data from the buffer it was parsed from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkRef<'a> {
+    chunk_type: ChunkType,
+    data: &'a [u8],
+    crc: u32,
+}
 
-    fn try_from(value: &[u8]) -> Result<Self> {
-        let mut value_iter = value.iter();
-        let mut bytes: Vec<u8> = value_iter.by_ref().take(4).map(|&b| b).collect();
-        let length = u32::from_be_bytes(bytes[..].try_into()?);
-        bytes = value_iter.by_ref().take(4).map(|&b| b).collect();
-
-        let bytes: [u8; 4] = bytes[..].try_into()?;
-        let chunk_type = ChunkType::try_from(bytes)?;
-        if !chunk_type.is_valid() {
+impl<'a> ChunkRef<'a> {
+    /// reads the chunk at the start of `bytes` without checking its type or
+    /// crc, returns it along with the number of bytes it spans
+    pub fn parse(bytes: &'a [u8]) -> Result<(ChunkRef<'a>, usize)> {
+        if bytes.len() < 12 {
             return Err(Box::new(ChunkError));
         }
-
-        let data: Vec<_> = value_iter.map(|&v| v).collect();
-        if data.len() < 4 {
+        let length = u32::from_be_bytes(bytes[..4].try_into()?);
+        if length > MAX_CHUNK_SIZE {
             return Err(Box::new(ChunkError));
         }
-
-        let (data, crc) = data.split_at(data.len() - 4);
-        if data.len() as u32 != length {
+        let length = length as usize;
+        let chunk_type = ChunkType::try_from(<[u8; 4]>::try_from(&bytes[4..8])?)?;
+        if bytes.len() - 12 < length {
             return Err(Box::new(ChunkError));
         }
-        let crc = u32::from_be_bytes(crc.try_into()?);
+        let data = &bytes[8..8 + length];
+        let crc = u32::from_be_bytes(bytes[8 + length..12 + length].try_into()?);
+
+        Ok((ChunkRef { chunk_type, data, crc }, 12 + length))
+    }
+
+    pub fn length(&self) -> u32 {
+        self.data.len() as u32
+    }
+
+    pub fn chunk_type(&self) -> &ChunkType {
+        &self.chunk_type
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn crc(&self) -> u32 {
+        self.crc
+    }
+
+    pub fn is_crc_valid(&self) -> bool {
         let mut digest = PNG32.digest();
-        digest.update(&[chunk_type.bytes().as_slice(), data].concat());
+        digest.update(&self.chunk_type.bytes());
+        digest.update(self.data);
+        digest.finalize() == self.crc
+    }
+
+    /// copies the data into an owned chunk, recomputing the crc
+    pub fn to_chunk(self) -> Chunk {
+        Chunk::new(self.chunk_type, self.data.to_vec())
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for ChunkRef<'a> {
+    type Error = Error;
 
-        if digest.finalize() != crc {
+    fn try_from(value: &'a [u8]) -> Result<Self> {
+        let (chunk, len) = ChunkRef::parse(value)?;
+        if len != value.len() || !chunk.chunk_type.is_valid() || !chunk.is_crc_valid() {
             return Err(Box::new(ChunkError));
         }
 
-        Ok(Chunk {
-            data: data.to_vec(),
-            length,
-            crc,
-            chunk_type,
-        })
+        Ok(chunk)
+    }
+}
+
+impl fmt::Display for ChunkRef<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Length {} Type {} CRC {}",
+            self.length(), self.chunk_type, self.crc
+        )
+    }
+}
+
+impl TryFrom<&[u8]> for Chunk {
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        let chunk = ChunkRef::try_from(value)?;
+        Ok(chunk.to_chunk())
     }
 }
 
@@ -104,6 +155,10 @@ impl Chunk {
         self.crc
     }
 
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+
     pub fn data_as_string(&self) -> Result<String> {
         Ok(String::from_utf8(self.data.clone())?)
     }
@@ -229,6 +284,35 @@ mod tests {
         assert!(chunk.is_err());
     }
 
+    #[test]
+    fn test_chunk_ref_borrows_data() {
+        let chunk = testing_chunk();
+        let bytes = chunk.as_bytes();
+        let chunk_ref = ChunkRef::try_from(&bytes[..]).unwrap();
+
+        assert_eq!(chunk_ref.length(), 42);
+        assert_eq!(chunk_ref.crc(), 2882656334);
+        assert_eq!(chunk_ref.data(), chunk.data());
+        assert!(std::ptr::eq(chunk_ref.data().as_ptr(), bytes[8..].as_ptr()));
+        assert_eq!(chunk_ref.to_string(), chunk.to_string());
+    }
+
+    #[test]
+    fn test_chunk_ref_parse_over_spec_limit() {
+        let mut bytes = testing_chunk().as_bytes();
+        bytes[..4].copy_from_slice(&(MAX_CHUNK_SIZE + 1).to_be_bytes());
+        assert!(ChunkRef::parse(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_chunk_ref_parse_truncated() {
+        let bytes = testing_chunk().as_bytes();
+        assert!(ChunkRef::parse(&bytes[..bytes.len() - 1]).is_err());
+
+        let (_, len) = ChunkRef::parse(&[&bytes[..], &bytes[..]].concat()).unwrap();
+        assert_eq!(len, bytes.len());
+    }
+
     #[test]
     pub fn test_chunk_trait_impls() {
         let data_length: u32 = 42;
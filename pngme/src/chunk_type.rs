@@ -4,7 +4,7 @@ use core::fmt;
 use std::convert;
 
 fn is_in_valid_range(b: u8) -> bool {
-    (b >= 65 && b <= 90) || (b >= 97 && b <= 122)
+    (65..=90).contains(&b) || (97..=122).contains(&b)
 }
 
 fn is_fifth_bit_set(b: u8) -> bool {
@@ -16,6 +16,13 @@ pub struct ChunkType {
     repr: [u8; 4],
 }
 
+/// chunk types registered by the PNG specification and its public extensions
+pub const STANDARD_TYPES: [&str; 31] = [
+    "IHDR", "PLTE", "IDAT", "IEND", "tRNS", "cHRM", "gAMA", "iCCP", "sBIT", "sRGB", "cICP",
+    "mDCV", "cLLI", "tEXt", "zTXt", "iTXt", "bKGD", "hIST", "pHYs", "sPLT", "eXIf", "tIME",
+    "acTL", "fcTL", "fdAT", "oFFs", "pCAL", "sCAL", "sTER", "gIFg", "gIFx",
+];
+
 impl ChunkType {
     pub fn bytes(&self) -> [u8; 4] {
         self.repr
@@ -51,10 +58,14 @@ impl ChunkType {
     pub fn is_safe_to_copy(&self) -> bool {
         is_fifth_bit_set(self.repr[3])
     }
+
+    pub fn is_standard(&self) -> bool {
+        STANDARD_TYPES.iter().any(|t| t.as_bytes() == self.repr)
+    }
 }
 
 #[derive(Debug)]
-struct ChunkTypeError;
+pub(crate) struct ChunkTypeError;
 
 impl fmt::Display for ChunkTypeError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -88,6 +99,9 @@ impl convert::TryFrom<[u8; 4]> for ChunkType {
     type Error = Error;
 
     fn try_from(value: [u8; 4]) -> Result<Self> {
+        if !value.iter().all(|&b| is_in_valid_range(b)) {
+            return Err(Box::new(ChunkTypeError));
+        }
         Ok(ChunkType { repr: value })
     }
 }
@@ -106,6 +120,12 @@ mod tests {
         assert_eq!(expected, actual.bytes());
     }
 
+    #[test]
+    pub fn test_chunk_type_from_invalid_bytes() {
+        assert!(ChunkType::try_from([0xff, 0xfe, 0x41, 0x41]).is_err());
+        assert!(ChunkType::try_from([82, 117, 83, b'1']).is_err());
+    }
+
     #[test]
     pub fn test_chunk_type_from_str() {
         let expected = ChunkType::try_from([82, 117, 83, 116]).unwrap();
@@ -176,6 +196,13 @@ mod tests {
         assert!(chunk.is_err());
     }
 
+    #[test]
+    pub fn test_chunk_type_is_standard() {
+        assert!(ChunkType::from_str("IDAT").unwrap().is_standard());
+        assert!(ChunkType::from_str("tEXt").unwrap().is_standard());
+        assert!(!ChunkType::from_str("RuSt").unwrap().is_standard());
+    }
+
     #[test]
     pub fn test_chunk_type_string() {
         let chunk = ChunkType::from_str("RuSt").unwrap();
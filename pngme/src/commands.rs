@@ -1,49 +1,433 @@
 use std::fs;
 use std::str::FromStr;
+use ed25519_dalek::VerifyingKey;
+use crate::carrier::{self, Carrier, Segment};
 use crate::png::Png;
-use crate::{png, chunk, chunk_type};
+use crate::chunk_type::ChunkType;
+use crate::{png, history, image, limits, lsb, optimize, palette, payload, reorder, signing, view, watermark};
 
 use crate::Result;
 
-fn decode_png(file_path: &str) -> Result<png::Png> {
-    let input = fs::read(file_path)?;
-    Png::try_from(&input[..])
+/// where `encode` hides a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Mode {
+    /// in a dedicated ancillary chunk
+    Chunk,
+    /// in the order of the palette entries of an indexed png
+    Palette,
+    /// in the least significant bits of pixels picked with a passphrase
+    Lsb,
 }
 
-pub fn encode(file_path: String, chunk_type: String, msg: String, output: Option<String>) -> Result<()> {
-    let mut png = decode_png(&file_path)?;
-    let chunk = chunk::Chunk::new(chunk_type::ChunkType::from_str(&chunk_type)?, msg.into_bytes());
-    png.append_chunk(chunk);
+/// where `encode` writes the file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Destination {
+    InPlace,
+    File(String),
+    /// reports where the message would go without writing anything
+    DryRun,
+}
+
+/// keyword of the tEXt chunk recording the type picked by `encode`
+const CHUNK_TYPE_KEYWORD: &[u8] = b"pngme\0";
+
+/// location signed into messages hidden in the palette order
+const PALETTE_LOCATION: &[u8] = b"PLTE";
+
+/// location signed into messages hidden in the pixel lsbs
+const LSB_LOCATION: &[u8] = b"IDAT";
+
+/// maps a file for read-only commands, they parse it with `PngRef` without copying
+fn map_file(file_path: &str) -> Result<memmap2::Mmap> {
+    let file = fs::File::open(file_path)?;
+    // SAFETY: pngme never writes through the map; a file truncated by another
+    // process while mapped is the usual caveat of mmap and is accepted here
+    Ok(unsafe { memmap2::Mmap::map(&file)? })
+}
 
-    if let Some(output_path) = output {
-        fs::write(output_path, png.as_bytes())?;
+/// tag stored in the segment written by `remember_tag`
+fn recorded_tag<'a>(format: carrier::Format, segment: &Segment<'a>) -> Option<&'a [u8]> {
+    if segment.tag == format.record_tag() {
+        segment.data.strip_prefix(CHUNK_TYPE_KEYWORD)
     } else {
-        fs::write(file_path, png.as_bytes())?;
+        None
     }
+}
 
-    Ok(())
+pub(crate) fn remembered_tag(format: carrier::Format, segments: &[Segment]) -> Option<String> {
+    segments
+        .iter()
+        .rev()
+        .find_map(|seg| recorded_tag(format, seg))
+        .and_then(|bytes| String::from_utf8(bytes.to_vec()).ok())
+}
+
+fn remember_tag(file: &mut dyn Carrier, tag: &str) -> Result<()> {
+    let format = file.format();
+    file.retain(&mut |seg| recorded_tag(format, seg).is_none());
+    file.insert(format.record_tag(), [CHUNK_TYPE_KEYWORD, tag.as_bytes()].concat())
 }
 
-pub fn decode(file_path: String, chunk_type: String) -> Result<String> {
-    let png = decode_png(&file_path)?;
-    let chunk = png.chunk_by_type(&chunk_type);
+/// removes the first segment with the given tag, and the note `encode` left
+/// for it once no segment of that tag remains
+pub(crate) fn remove_from(file: &mut dyn Carrier, tag: &str) -> Result<Vec<u8>> {
+    let data = file.remove(tag)?;
+    let format = file.format();
+    let segments = file.segments();
+    if remembered_tag(format, &segments).as_deref() == Some(tag) && segments.iter().all(|seg| seg.tag != tag) {
+        file.retain(&mut |seg| recorded_tag(format, seg).is_none());
+    }
 
-    if let Some(ch) = chunk {
-        return ch.data_as_string();
-    } else {
-        return Err(Box::new(png::PngError { msg: "cannot decode given chunk_type" }));
+    Ok(data)
+}
+
+/// adds `msg` to `file` and returns the tag of the segment it was stored in
+pub(crate) fn encode_into(file: &mut dyn Carrier, tag: Option<String>, msg: &[u8], options: &payload::Options) -> Result<String> {
+    let tag = match tag {
+        Some(tag) => {
+            file.check_tag(&tag)?;
+            tag
+        }
+        None => {
+            let tag = file.free_tag().ok_or(carrier::CarrierError { msg: "no free chunk type left" })?;
+            remember_tag(file, &tag)?;
+            tag
+        }
+    };
+    let data = payload::wrap(msg, &file.signed_location(&tag), options)?;
+    file.insert(&tag, data)?;
+
+    Ok(tag)
+}
+
+fn pixel_png(input: &[u8]) -> Result<&[u8]> {
+    if carrier::Format::detect(input)? != carrier::Format::Png {
+        return Err(Box::new(png::PngError { msg: "palette, lsb and watermark modes only work on png files" }));
+    }
+    Ok(input)
+}
+
+fn lsb_passphrase(passphrase: Option<&str>) -> Result<&[u8]> {
+    passphrase
+        .map(str::as_bytes)
+        .ok_or_else(|| Box::new(png::PngError { msg: "lsb mode needs a passphrase" }).into())
+}
+
+/// hides `msg` in the pixels of `input`, returns the png and what it cost
+fn lsb_embed(input: &[u8], msg: &str, passphrase: Option<&str>, options: &payload::Options) -> Result<(Png, lsb::Estimate)> {
+    let mut png = Png::try_from(pixel_png(input)?)?;
+    let mut image = png.decode_pixels()?;
+    let data = payload::wrap(msg.as_bytes(), LSB_LOCATION, options)?;
+    let estimate = lsb::embed(&mut image, &data, lsb_passphrase(passphrase)?)?;
+    png.encode_pixels(&image)?;
+    Ok((png, estimate))
+}
+
+/// capacity and detectability of hiding `msg` in lsb mode, nothing is written;
+/// `encode` with the same arguments makes exactly these changes
+pub fn lsb_estimate(file_path: &str, msg: &str, passphrase: Option<&str>, options: &payload::Options) -> Result<lsb::Estimate> {
+    Ok(lsb_embed(&fs::read(file_path)?, msg, passphrase, options)?.1)
+}
+
+/// returns where the message was hidden
+pub fn encode(
+    file_path: String,
+    mode: Mode,
+    chunk_type: Option<String>,
+    msg: String,
+    passphrase: Option<&str>,
+    destination: Destination,
+    options: &payload::Options,
+) -> Result<String> {
+    let input = fs::read(&file_path)?;
+    let (location, bytes) = match mode {
+        Mode::Chunk => {
+            let mut file = carrier::parse(&input)?;
+            let tag = encode_into(file.as_mut(), chunk_type, msg.as_bytes(), options)?;
+            (format!("chunk {}", tag), file.as_bytes())
+        }
+        Mode::Palette => {
+            let mut png = Png::try_from(pixel_png(&input)?)?;
+            palette::embed(&mut png, &payload::wrap(msg.as_bytes(), PALETTE_LOCATION, options)?)?;
+            (String::from("palette"), png.as_bytes())
+        }
+        Mode::Lsb => (String::from("pixel lsbs"), lsb_embed(&input, &msg, passphrase, options)?.0.as_bytes()),
+    };
+
+    match destination {
+        Destination::InPlace => fs::write(file_path, bytes)?,
+        Destination::File(output) => fs::write(output, bytes)?,
+        Destination::DryRun => {}
+    }
+
+    Ok(location)
+}
+
+/// unwraps the payload in the segment of type `chunk_type`, or in the one
+/// recorded by `encode`; returns it with the location its signature covers
+pub(crate) fn find_payload(
+    format: carrier::Format,
+    segments: &[Segment],
+    chunk_type: Option<String>,
+) -> Result<(payload::Payload, Vec<u8>)> {
+    let tag = match chunk_type {
+        Some(tag) => tag,
+        None => remembered_tag(format, segments).ok_or(png::PngError { msg: "no chunk type recorded by encode" })?,
+    };
+    let segment = segments
+        .iter()
+        .find(|seg| seg.tag == tag)
+        .ok_or(png::PngError { msg: "cannot decode given chunk_type" })?;
+
+    Ok((payload::unwrap(segment.data)?, tag.into_bytes()))
+}
+
+/// the location a message in the first `tag` segment of `input` was signed with
+pub(crate) fn signed_location(input: &[u8], tag: &str) -> Result<Vec<u8>> {
+    let mut file = carrier::parse(input)?;
+    file.remove(tag)?;
+    Ok(file.signed_location(tag))
+}
+
+/// decodes the message, checking its signature against `verify` when given
+pub fn decode(
+    file_path: String,
+    mode: Mode,
+    chunk_type: Option<String>,
+    passphrase: Option<&str>,
+    verify: Option<&VerifyingKey>,
+) -> Result<payload::Payload> {
+    let input = map_file(&file_path)?;
+    let (payload, location) = match mode {
+        Mode::Chunk => {
+            let (format, segments) = carrier::segments(&input)?;
+            let (payload, tag) = find_payload(format, &segments, chunk_type)?;
+            match verify {
+                Some(_) => (payload, signed_location(&input, &String::from_utf8(tag)?)?),
+                None => (payload, tag),
+            }
+        }
+        Mode::Palette => {
+            let png = png::PngRef::try_from(pixel_png(&input)?)?;
+            (payload::unwrap(&palette::extract(&png)?)?, PALETTE_LOCATION.to_vec())
+        }
+        Mode::Lsb => {
+            let image = png::PngRef::try_from(pixel_png(&input)?)?.decode_pixels()?;
+            (payload::unwrap(&lsb::extract(&image, lsb_passphrase(passphrase)?)?)?, LSB_LOCATION.to_vec())
+        }
+    };
+
+    if let Some(key) = verify {
+        payload.verify(key, &location)?;
+    }
+
+    Ok(payload)
+}
+
+/// checks a file against its specification before it is touched: every png
+/// chunk must have a valid type and crc and stay within the default `Limits`,
+/// and the chunks must follow the ordering rules, nothing after `IEND`
+pub fn validate(file_path: &str) -> Result<()> {
+    validate_bytes(&map_file(file_path)?)
+}
+
+/// [`validate`] for a file already in memory
+pub fn validate_bytes(input: &[u8]) -> Result<()> {
+    if carrier::Format::detect(input)? != carrier::Format::Png {
+        carrier::parse(input)?;
+        return Ok(());
+    }
+
+    let png = png::PngRef::parse_with_limits(input, &limits::Limits::default())?;
+    if png.chunks().iter().any(|ch| !ch.chunk_type().is_valid()) {
+        return Err(Box::new(png::PngError { msg: "chunk type has the reserved bit set" }));
     }
+    if png.chunks().iter().any(|ch| !ch.is_crc_valid()) {
+        return Err(Box::new(png::PngError { msg: "chunk crc does not match its data" }));
+    }
+    let types: Vec<String> = png.chunks().iter().map(|ch| ch.chunk_type().to_string()).collect();
+    if let Some(&msg) = reorder::violations(&types).first() {
+        return Err(Box::new(png::PngError { msg }));
+    }
+
+    Ok(())
+}
+
+/// generates a signing key pair, returns the paths it was written to
+pub fn keygen(prefix: String) -> Result<(String, String)> {
+    signing::write_key_pair(&signing::generate_key()?, &prefix)
 }
 
 pub fn remove(file_path: String, chunk_type: String) -> Result<String> {
-    let mut png = decode_png(&file_path)?;
-    let chunk = png.remove_chunk(&chunk_type)?;
+    let mut file = carrier::parse(&fs::read(&file_path)?)?;
+    let data = remove_from(file.as_mut(), &chunk_type)?;
+    fs::write(file_path, file.as_bytes())?;
+
+    Ok(String::from_utf8_lossy(&data).into_owned())
+}
+
+/// reorders the chunks, returns the new order
+pub fn reorder(file_path: String, order: &reorder::Order) -> Result<String> {
+    let png = reorder::reorder(Png::try_from(&fs::read(&file_path)?[..])?, order)?;
+    fs::write(file_path, png.as_bytes())?;
+    let types: Vec<String> = png.chunks().iter().map(|ch| ch.chunk_type().to_string()).collect();
+
+    Ok(format!("chunks reordered: {}", types.join(" ")))
+}
+
+/// changes the type of the first chunk of type `old`, which may flip its
+/// private and safe-to-copy bits; critical chunks are never renamed
+pub fn rename_type(file_path: String, old: String, new: String) -> Result<String> {
+    let mut png = Png::try_from(&fs::read(&file_path)?[..])?;
+    let (old_type, new_type) = (ChunkType::from_str(&old)?, ChunkType::from_str(&new)?);
+    if old_type.is_critical() || new_type.is_critical() {
+        return Err(Box::new(png::PngError { msg: "critical chunks cannot be renamed" }));
+    }
+    if !new_type.is_valid() {
+        return Err(Box::new(png::PngError { msg: "chunk type has the reserved bit set" }));
+    }
+
+    png.rename_chunk(&old, new_type)?;
+    let remembered = remembered_tag(carrier::Format::Png, &png.segments());
+    if remembered.as_deref() == Some(old.as_str()) && png.chunk_by_type(&old).is_none() {
+        remember_tag(&mut png, &new)?;
+    }
     fs::write(file_path, png.as_bytes())?;
 
-    chunk.data_as_string()
+    Ok(format!(
+        "renamed {} to {} ({}, {})",
+        old,
+        new,
+        if new_type.is_public() { "public" } else { "private" },
+        if new_type.is_safe_to_copy() { "safe to copy" } else { "unsafe to copy" },
+    ))
+}
+
+/// recompresses the image data, returns a report of the bytes saved
+pub fn optimize(file_path: String, output: Option<String>, options: &optimize::Options) -> Result<String> {
+    let mut png = Png::try_from(&fs::read(&file_path)?[..])?;
+    let report = optimize::optimize(&mut png, options)?;
+    fs::write(output.unwrap_or(file_path), png.as_bytes())?;
+
+    Ok(report.to_string())
+}
+
+fn decode_image(file_path: &str) -> Result<image::Image> {
+    let input = map_file(file_path)?;
+    image::Image::decode_rgba(&png::PngRef::try_from(&input[..])?)
+}
+
+/// draws a preview of the image for the terminal
+pub fn view(file_path: String, columns: u32, lsb: bool, diff: Option<String>) -> Result<String> {
+    let image = decode_image(&file_path)?;
+    let overlay = match diff {
+        Some(original) => view::Overlay::Diff(decode_image(&original)?),
+        None if lsb => view::Overlay::LsbPlane,
+        None => view::Overlay::None,
+    };
+
+    view::render(&image, columns, &overlay)
+}
+
+/// bytes that can be hidden in the file with every mode that has a fixed capacity
+pub fn capacity(file_path: String) -> Result<String> {
+    let input = map_file(&file_path)?;
+    let png = || pixel_png(&input).and_then(png::PngRef::try_from);
+    let describe = |bytes: Result<usize>| match bytes {
+        Ok(bytes) => format!("{} bytes", bytes),
+        Err(e) => format!("unavailable, {}", e),
+    };
+    let palette = describe(png().and_then(|png| palette::capacity_of(&png)));
+    let lsb = describe(png().and_then(|png| lsb::capacity(&png.decode_pixels()?)));
+
+    Ok(format!("palette: {}, lsb: {}", palette, lsb))
+}
+
+/// adds an invisible mark carrying `id` to the pixels
+pub fn watermark_embed(file_path: String, id: u32, key: &str, strength: f32, output: Option<String>) -> Result<String> {
+    let input = fs::read(&file_path)?;
+    let mut png = Png::try_from(pixel_png(&input)?)?;
+    let mut image = png.decode_pixels()?;
+    watermark::embed(&mut image, id, key.as_bytes(), strength)?;
+    png.encode_pixels(&image)?;
+    fs::write(output.unwrap_or(file_path), png.as_bytes())?;
+
+    Ok(format!("watermarked with id {}", id))
+}
+
+pub fn watermark_detect(file_path: String, key: &str) -> Result<watermark::Detection> {
+    let input = map_file(&file_path)?;
+    let png = png::PngRef::try_from(pixel_png(&input)?)?;
+    watermark::detect(&image::Image::decode_rgba(&png)?, key.as_bytes())
+}
+
+/// lists the history entries of a png once the chain they form checks out
+pub fn history(file_path: String, verify: Option<&VerifyingKey>) -> Result<String> {
+    let input = map_file(&file_path)?;
+    let png = png::PngRef::try_from(&input[..])?.to_png();
+    let entries = history::verify(&png, verify)?;
+    if entries.is_empty() {
+        return Ok(String::from("no history recorded"));
+    }
+    let lines: Vec<_> = entries.iter().enumerate().map(|(i, entry)| format!("{:>3}. {}", i + 1, entry)).collect();
+
+    Ok(format!("{}\nchain verified", lines.join("\n")))
+}
+
+pub fn print(file_path: String) -> Result<String> {
+    let input = map_file(&file_path)?;
+    if carrier::Format::detect(&input)? == carrier::Format::Png {
+        return Ok(png::PngRef::try_from(&input[..])?.to_string());
+    }
+    let (_, segments) = carrier::segments(&input)?;
+    let out: Vec<_> = segments.iter().map(|seg| seg.to_string()).collect();
+
+    Ok(out.join(" "))
+}
+
+/// pixel data for a png built by `create`
+pub enum Source {
+    Solid([u8; 4]),
+    Noise(u64),
+    /// file with 8 bit rgba pixels
+    Raw(String),
+}
+
+/// parses `RRGGBB` or `RRGGBBAA`, optionally prefixed by `#`
+pub fn parse_color(color: &str) -> Result<[u8; 4]> {
+    let hex = color.trim_start_matches('#');
+    if !(hex.len() == 6 || hex.len() == 8) || !hex.is_ascii() {
+        return Err(Box::new(png::PngError { msg: "colors must look like RRGGBB or RRGGBBAA" }));
+    }
+
+    let mut rgba = [255; 4];
+    for (i, byte) in rgba.iter_mut().take(hex.len() / 2).enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)?;
+    }
+
+    Ok(rgba)
 }
 
-pub fn print(file_path: String) {
-    let png = decode_png(&file_path).expect("cannot read provided png file");
-    println!("{}", png);
+pub fn create(
+    output: String,
+    width: u32,
+    height: u32,
+    color_type: image::ColorType,
+    source: Source,
+    message: Option<(Option<String>, String, payload::Options)>,
+) -> Result<Option<String>> {
+    let image = match source {
+        Source::Solid(rgba) => image::Image::solid(width, height, rgba, color_type)?,
+        Source::Noise(seed) => image::Image::noise(width, height, seed, color_type)?,
+        Source::Raw(path) => image::Image::from_rgba(width, height, &fs::read(path)?, color_type)?,
+    };
+    let mut png = Png::from_image(&image)?;
+
+    let chunk_type = match message {
+        Some((chunk_type, msg, options)) => Some(encode_into(&mut png, chunk_type, msg.as_bytes(), &options)?),
+        None => None,
+    };
+    fs::write(output, png.as_bytes())?;
+
+    Ok(chunk_type)
 }
@@ -0,0 +1,213 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+use serde::Deserialize;
+
+use pngme::payload::Compression;
+use pngme::Result;
+
+const FILE_NAME: &str = "pngme.toml";
+
+#[derive(Debug)]
+pub struct ConfigError {
+    pub msg: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "error while reading configuration: {}", self.msg)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// how much a file has to conform to its specification before it is touched
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Strictness {
+    /// accepts anything pngme can parse, crc mismatches are fixed on write
+    #[default]
+    Lenient,
+    /// refuses files with bad crcs, misplaced chunks or oversized streams
+    Strict,
+}
+
+/// defaults read from `pngme.toml`, options given on the command line win
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub chunk_type: Option<String>,
+    pub ecc: Option<u8>,
+    pub compress: Option<Compression>,
+    /// secret key for `--sign`, relative to the file it is set in
+    pub sign_key: Option<PathBuf>,
+    /// public key for `--verify`, relative to the file it is set in
+    pub verify_key: Option<PathBuf>,
+    /// output path of `encode` and `optimize`, with `{dir}`, `{stem}` and
+    /// `{ext}` taken from the input path
+    pub output: Option<String>,
+    pub strictness: Option<Strictness>,
+    /// ancillary chunk types `optimize --strip` keeps on top of the usual ones
+    pub strip_keep: Option<Vec<String>>,
+    /// records every write in the history chunk of the file
+    pub history: Option<bool>,
+    /// secret key signing history entries, relative to the file it is set in
+    pub history_key: Option<PathBuf>,
+    /// named sets of options selected with `--profile`
+    pub profiles: BTreeMap<String, Config>,
+}
+
+impl Config {
+    /// values of `other` replace those of `self`, profiles are merged by name
+    fn merge(self, other: Config) -> Config {
+        let mut profiles = self.profiles;
+        for (name, profile) in other.profiles {
+            let merged = profiles.remove(&name).unwrap_or_default().merge(profile);
+            profiles.insert(name, merged);
+        }
+
+        Config {
+            chunk_type: other.chunk_type.or(self.chunk_type),
+            ecc: other.ecc.or(self.ecc),
+            compress: other.compress.or(self.compress),
+            sign_key: other.sign_key.or(self.sign_key),
+            verify_key: other.verify_key.or(self.verify_key),
+            output: other.output.or(self.output),
+            strictness: other.strictness.or(self.strictness),
+            strip_keep: other.strip_keep.or(self.strip_keep),
+            history: other.history.or(self.history),
+            history_key: other.history_key.or(self.history_key),
+            profiles,
+        }
+    }
+
+    fn resolve_keys(&mut self, dir: &Path) {
+        for key in [&mut self.sign_key, &mut self.verify_key, &mut self.history_key].into_iter().flatten() {
+            *key = dir.join(&*key);
+        }
+        self.profiles.values_mut().for_each(|profile| profile.resolve_keys(dir));
+    }
+
+    fn parse(text: &str, dir: &Path) -> Result<Config> {
+        let mut config: Config = toml::from_str(text).map_err(|e| ConfigError { msg: e.to_string() })?;
+        if config.profiles.values().any(|profile| !profile.profiles.is_empty()) {
+            return Err(Box::new(ConfigError { msg: String::from("profiles cannot hold profiles") }));
+        }
+        config.resolve_keys(dir);
+
+        Ok(config)
+    }
+
+    /// applies the options of the given profile on top of the defaults
+    pub fn select(mut self, profile: Option<&str>) -> Result<Config> {
+        let profiles = std::mem::take(&mut self.profiles);
+        match profile {
+            Some(name) => match profiles.get(name) {
+                Some(profile) => Ok(self.merge(profile.clone())),
+                None => Err(Box::new(ConfigError { msg: format!("no profile named {}", name) })),
+            },
+            None => Ok(self),
+        }
+    }
+}
+
+/// `pngme.toml` in `$XDG_CONFIG_HOME/pngme` or `~/.config/pngme`
+fn user_file() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(base.join("pngme").join(FILE_NAME))
+}
+
+/// nearest `pngme.toml` in `dir` or one of its parents
+fn directory_file(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors().map(|dir| dir.join(FILE_NAME)).find(|path| path.is_file())
+}
+
+fn read(path: &Path) -> Result<Config> {
+    let text = fs::read_to_string(path)?;
+    Config::parse(&text, path.parent().unwrap_or(Path::new(".")))
+        .map_err(|e| Box::new(ConfigError { msg: format!("{}: {}", path.display(), e) }).into())
+}
+
+/// reads the user file, then the nearest directory file, which overrides it
+pub fn load(profile: Option<&str>) -> Result<Config> {
+    let mut config = Config::default();
+    for path in user_file().into_iter().chain(directory_file(&env::current_dir()?)) {
+        if path.is_file() {
+            config = config.merge(read(&path)?);
+        }
+    }
+
+    config.select(profile)
+}
+
+/// fills the `output` pattern in for `input`
+pub fn output_path(pattern: &str, input: &Path) -> String {
+    let dir = match input.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_string_lossy(),
+        _ => ".".into(),
+    };
+    let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = input.extension().unwrap_or_default().to_string_lossy();
+
+    pattern.replace("{dir}", &dir).replace("{stem}", &stem).replace("{ext}", &ext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = r#"
+        chunk-type = "ruSt"
+        sign-key = "keys/team.key"
+        strictness = "strict"
+        history-key = "keys/audit.key"
+
+        [profiles.release]
+        chunk-type = "prOd"
+        ecc = 16
+        compress = "zstd"
+        strip-keep = ["tIME"]
+    "#;
+
+    #[test]
+    fn test_parse_resolves_keys() {
+        let config = Config::parse(SAMPLE, Path::new("/project")).unwrap();
+        assert_eq!(config.chunk_type.as_deref(), Some("ruSt"));
+        assert_eq!(config.sign_key, Some(PathBuf::from("/project/keys/team.key")));
+        assert_eq!(config.history_key, Some(PathBuf::from("/project/keys/audit.key")));
+        assert_eq!(config.strictness, Some(Strictness::Strict));
+        assert!(Config::parse("chunk_typ = \"ruSt\"", Path::new(".")).is_err());
+        assert!(Config::parse("[profiles.a.profiles.b]", Path::new(".")).is_err());
+    }
+
+    #[test]
+    fn test_profile_overrides_defaults() {
+        let config = Config::parse(SAMPLE, Path::new("/project")).unwrap();
+        let release = config.clone().select(Some("release")).unwrap();
+        assert_eq!(release.chunk_type.as_deref(), Some("prOd"));
+        assert_eq!(release.ecc, Some(16));
+        assert_eq!(release.compress, Some(Compression::Zstd));
+        assert_eq!(release.sign_key, config.sign_key);
+        assert!(config.select(Some("debug")).is_err());
+    }
+
+    #[test]
+    fn test_directory_file_overrides_user_file() {
+        let user = Config::parse(SAMPLE, Path::new("/home")).unwrap();
+        let local = Config::parse("ecc = 4\n[profiles.release]\nchunk-type = \"loCl\"", Path::new("/project")).unwrap();
+        let merged = user.merge(local).select(Some("release")).unwrap();
+        assert_eq!(merged.chunk_type.as_deref(), Some("loCl"));
+        assert_eq!(merged.ecc, Some(16));
+        assert_eq!(merged.strip_keep, Some(vec![String::from("tIME")]));
+    }
+
+    #[test]
+    fn test_output_path() {
+        assert_eq!(output_path("{dir}/{stem}.secret.{ext}", Path::new("img/cat.png")), "img/cat.secret.png");
+        assert_eq!(output_path("{dir}/{stem}.secret.{ext}", Path::new("cat.png")), "./cat.secret.png");
+    }
+}
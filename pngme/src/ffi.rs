@@ -0,0 +1,305 @@
+//! c api of the `cdylib`, the build script generates `include/pngme.h` from it
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use crate::carrier::{Carrier, Format};
+use crate::png::Png;
+use crate::{commands, payload, Error};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PngmeStatus {
+    Ok = 0,
+    NullPointer = 1,
+    /// a chunk type that is not valid or not allowed to hold a message
+    InvalidArgument = 2,
+    InvalidPng = 3,
+    NotFound = 4,
+    Failed = 5,
+    Panic = 6,
+}
+
+/// a parsed png, owned by the caller until passed to `pngme_free`
+pub struct PngmePng {
+    png: Png,
+}
+
+#[repr(C)]
+pub struct PngmeChunk {
+    /// nul terminated
+    pub chunk_type: [c_char; 5],
+    pub length: u32,
+    pub crc: u32,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+struct Failure(PngmeStatus, String);
+
+fn fail(status: PngmeStatus) -> impl FnOnce(Error) -> Failure {
+    move |e| Failure(status, e.to_string())
+}
+
+fn null_check<T>(ptr: *const T) -> Result<(), Failure> {
+    if ptr.is_null() {
+        return Err(Failure(PngmeStatus::NullPointer, String::from("unexpected null pointer")));
+    }
+    Ok(())
+}
+
+/// runs `f`, storing its error for `pngme_last_error`; panics never cross the ffi boundary
+fn guard<F: FnOnce() -> Result<(), Failure>>(f: F) -> PngmeStatus {
+    let (status, msg) = match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => (PngmeStatus::Ok, String::new()),
+        Ok(Err(Failure(status, msg))) => (status, msg),
+        Err(_) => (PngmeStatus::Panic, String::from("pngme panicked")),
+    };
+    let msg = CString::new(msg.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = msg);
+
+    status
+}
+
+/// reads an optional chunk type, `None` for a null pointer
+unsafe fn optional_str(ptr: *const c_char) -> Result<Option<String>, Failure> {
+    if ptr.is_null() {
+        return Ok(None);
+    }
+    match CStr::from_ptr(ptr).to_str() {
+        Ok(s) => Ok(Some(s.to_owned())),
+        Err(_) => Err(Failure(PngmeStatus::InvalidArgument, String::from("chunk type is not utf-8"))),
+    }
+}
+
+unsafe fn give_bytes(bytes: Vec<u8>, data: *mut *mut u8, len: *mut usize) {
+    *len = bytes.len();
+    *data = Box::into_raw(bytes.into_boxed_slice()) as *mut u8;
+}
+
+/// message of the last failed call on this thread, empty after a successful
+/// one; valid until the next call
+#[no_mangle]
+pub extern "C" fn pngme_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ptr())
+}
+
+/// parses `len` bytes at `data`, which are copied
+///
+/// # Safety
+/// `data` must point to `len` readable bytes and `out` to writable memory
+#[no_mangle]
+pub unsafe extern "C" fn pngme_open(data: *const u8, len: usize, out: *mut *mut PngmePng) -> PngmeStatus {
+    guard(|| {
+        null_check(data)?;
+        null_check(out)?;
+        let png = Png::try_from(std::slice::from_raw_parts(data, len)).map_err(fail(PngmeStatus::InvalidPng))?;
+        *out = Box::into_raw(Box::new(PngmePng { png }));
+        Ok(())
+    })
+}
+
+/// lists every chunk, the array must be released with `pngme_free_chunks`
+///
+/// # Safety
+/// `png` must come from `pngme_open`, `chunks` and `count` must be writable
+#[no_mangle]
+pub unsafe extern "C" fn pngme_list_chunks(png: *const PngmePng, chunks: *mut *mut PngmeChunk, count: *mut usize) -> PngmeStatus {
+    guard(|| {
+        null_check(png)?;
+        null_check(chunks)?;
+        null_check(count)?;
+        let list: Vec<PngmeChunk> = (*png)
+            .png
+            .chunks()
+            .iter()
+            .map(|ch| {
+                let mut chunk_type = [0; 5];
+                for (dst, src) in chunk_type.iter_mut().zip(ch.chunk_type().bytes()) {
+                    *dst = src as c_char;
+                }
+                PngmeChunk { chunk_type, length: ch.length(), crc: ch.crc() }
+            })
+            .collect();
+        *count = list.len();
+        *chunks = Box::into_raw(list.into_boxed_slice()) as *mut PngmeChunk;
+        Ok(())
+    })
+}
+
+/// hides `message` in a chunk of type `chunk_type`, or in a free private
+/// chunk type when it is null; the type used is written to `used_type` unless
+/// it is null
+///
+/// # Safety
+/// `png` must come from `pngme_open`, `chunk_type` must be null or nul
+/// terminated, `message` must point to `len` bytes and `used_type` must be
+/// null or hold 5 bytes
+#[no_mangle]
+pub unsafe extern "C" fn pngme_encode(
+    png: *mut PngmePng,
+    chunk_type: *const c_char,
+    message: *const u8,
+    len: usize,
+    used_type: *mut c_char,
+) -> PngmeStatus {
+    guard(|| {
+        null_check(png)?;
+        null_check(message)?;
+        let chunk_type = optional_str(chunk_type)?;
+        if let Some(chunk_type) = &chunk_type {
+            (*png).png.check_tag(chunk_type).map_err(fail(PngmeStatus::InvalidArgument))?;
+        }
+
+        let message = std::slice::from_raw_parts(message, len);
+        let tag = commands::encode_into(&mut (*png).png, chunk_type, message, &payload::Options::default())
+            .map_err(fail(PngmeStatus::Failed))?;
+        if !used_type.is_null() {
+            let used_type = std::slice::from_raw_parts_mut(used_type, 5);
+            for (dst, src) in used_type.iter_mut().zip(tag.bytes().chain([0])) {
+                *dst = src as c_char;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// decodes the message in the chunk of type `chunk_type`, or the one recorded
+/// by an earlier encode when it is null; release it with `pngme_free_bytes`
+///
+/// # Safety
+/// `png` must come from `pngme_open`, `chunk_type` must be null or nul
+/// terminated, `message` and `len` must be writable
+#[no_mangle]
+pub unsafe extern "C" fn pngme_decode(
+    png: *const PngmePng,
+    chunk_type: *const c_char,
+    message: *mut *mut u8,
+    len: *mut usize,
+) -> PngmeStatus {
+    guard(|| {
+        null_check(png)?;
+        null_check(message)?;
+        null_check(len)?;
+        let segments = (*png).png.segments();
+        let tag = match optional_str(chunk_type)? {
+            Some(tag) => tag,
+            None => commands::remembered_tag(Format::Png, &segments)
+                .ok_or_else(|| Failure(PngmeStatus::NotFound, String::from("no chunk type recorded by encode")))?,
+        };
+        let segment = segments
+            .iter()
+            .find(|seg| seg.tag == tag)
+            .ok_or_else(|| Failure(PngmeStatus::NotFound, String::from("chunk type not found")))?;
+
+        let payload = payload::unwrap(segment.data).map_err(fail(PngmeStatus::Failed))?;
+        give_bytes(payload.message, message, len);
+        Ok(())
+    })
+}
+
+/// serializes the png, release the bytes with `pngme_free_bytes`
+///
+/// # Safety
+/// `png` must come from `pngme_open`, `data` and `len` must be writable
+#[no_mangle]
+pub unsafe extern "C" fn pngme_to_bytes(png: *const PngmePng, data: *mut *mut u8, len: *mut usize) -> PngmeStatus {
+    guard(|| {
+        null_check(png)?;
+        null_check(data)?;
+        null_check(len)?;
+        give_bytes((*png).png.as_bytes(), data, len);
+        Ok(())
+    })
+}
+
+/// # Safety
+/// `data` and `len` must come from `pngme_decode` or `pngme_to_bytes`
+#[no_mangle]
+pub unsafe extern "C" fn pngme_free_bytes(data: *mut u8, len: usize) {
+    if !data.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(data, len)));
+    }
+}
+
+/// # Safety
+/// `chunks` and `count` must come from `pngme_list_chunks`
+#[no_mangle]
+pub unsafe extern "C" fn pngme_free_chunks(chunks: *mut PngmeChunk, count: usize) {
+    if !chunks.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(chunks, count)));
+    }
+}
+
+/// # Safety
+/// `png` must be null or come from `pngme_open`, it is invalid afterwards
+#[no_mangle]
+pub unsafe extern "C" fn pngme_free(png: *mut PngmePng) {
+    if !png.is_null() {
+        drop(Box::from_raw(png));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::{ColorType, Image};
+
+    fn open_sample() -> *mut PngmePng {
+        let bytes = Png::from_image(&Image::solid(2, 2, [0; 4], ColorType::Rgb).unwrap()).unwrap().as_bytes();
+        let mut png = ptr::null_mut();
+        assert_eq!(unsafe { pngme_open(bytes.as_ptr(), bytes.len(), &mut png) }, PngmeStatus::Ok);
+        png
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let png = open_sample();
+        let mut used = [0 as c_char; 5];
+        let status = unsafe { pngme_encode(png, ptr::null(), b"hi".as_ptr(), 2, used.as_mut_ptr()) };
+        assert_eq!(status, PngmeStatus::Ok);
+        assert_eq!(unsafe { CStr::from_ptr(used.as_ptr()) }.to_str().unwrap(), "pmAa");
+
+        let (mut data, mut len) = (ptr::null_mut(), 0);
+        assert_eq!(unsafe { pngme_decode(png, ptr::null(), &mut data, &mut len) }, PngmeStatus::Ok);
+        assert_eq!(unsafe { std::slice::from_raw_parts(data, len) }, b"hi");
+        unsafe {
+            pngme_free_bytes(data, len);
+            pngme_free(png);
+        }
+    }
+
+    #[test]
+    fn test_list_chunks() {
+        let png = open_sample();
+        let (mut chunks, mut count) = (ptr::null_mut(), 0);
+        assert_eq!(unsafe { pngme_list_chunks(png, &mut chunks, &mut count) }, PngmeStatus::Ok);
+
+        let list = unsafe { std::slice::from_raw_parts(chunks, count) };
+        let first = unsafe { CStr::from_ptr(list[0].chunk_type.as_ptr()) };
+        assert_eq!((count, first.to_str().unwrap(), list[0].length), (3, "IHDR", 13));
+        unsafe {
+            pngme_free_chunks(chunks, count);
+            pngme_free(png);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut png = ptr::null_mut();
+        assert_eq!(unsafe { pngme_open(b"nope".as_ptr(), 4, &mut png) }, PngmeStatus::InvalidPng);
+        assert!(!unsafe { CStr::from_ptr(pngme_last_error()) }.to_bytes().is_empty());
+        assert_eq!(unsafe { pngme_open(ptr::null(), 0, &mut png) }, PngmeStatus::NullPointer);
+
+        let png = open_sample();
+        let status = unsafe { pngme_encode(png, c"IDAT".as_ptr(), b"x".as_ptr(), 1, ptr::null_mut()) };
+        assert_eq!(status, PngmeStatus::InvalidArgument);
+        let (mut data, mut len) = (ptr::null_mut(), 0);
+        assert_eq!(unsafe { pngme_decode(png, ptr::null(), &mut data, &mut len) }, PngmeStatus::NotFound);
+        unsafe { pngme_free(png) };
+    }
+}
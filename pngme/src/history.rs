@@ -0,0 +1,394 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use sha2::{Digest, Sha256};
+
+use crate::carrier::Format;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use crate::Result;
+
+// Every recorded write appends one `hiSt` chunk holding lines of `name value`:
+// the operation, a unix timestamp, the tool version, the chunk types touched,
+// and the hashes of the file before and after the write. Hashes cover the
+// file without its `hiSt` chunks, so each entry starts where the one before
+// it ended and a write that was not recorded breaks the chain. With a key the
+// entry is signed, the signature covering every line before it.
+
+/// private, safe to copy: the history survives tools that keep unknown chunks
+pub const HISTORY_CHUNK: &str = "hiSt";
+
+#[derive(Debug)]
+pub struct HistoryError {
+    pub msg: String,
+}
+
+impl fmt::Display for HistoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "error while checking history: {}", self.msg)
+    }
+}
+
+impl std::error::Error for HistoryError {}
+
+fn history_error(msg: String) -> Box<HistoryError> {
+    Box::new(HistoryError { msg })
+}
+
+/// one recorded write
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub operation: String,
+    /// seconds since the unix epoch
+    pub time: u64,
+    pub tool: String,
+    pub touched: Vec<String>,
+    /// hash of the file before the write, `None` for files pngme created
+    pub previous: Option<String>,
+    pub result: String,
+    pub key: Option<VerifyingKey>,
+    pub signature: Option<Signature>,
+}
+
+/// days since the unix epoch as year, month and day, after Howard Hinnant's `civil_from_days`
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + (month <= 2) as u64, month, day)
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (year, month, day) = civil_date(self.time / 86_400);
+        let seconds = self.time % 86_400;
+        write!(
+            f,
+            "{} on {:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC with {}",
+            self.operation,
+            year,
+            month,
+            day,
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60,
+            self.tool
+        )?;
+        if !self.touched.is_empty() {
+            write!(f, ", touched {}", self.touched.join(" "))?;
+        }
+        match self.key {
+            Some(key) => write!(f, ", signed by {}", &hex::encode(key.to_bytes())[..16]),
+            None => write!(f, ", unsigned"),
+        }
+    }
+}
+
+impl Entry {
+    /// every line but the signature, which is what gets signed
+    fn signed_text(&self) -> String {
+        let mut text = format!(
+            "operation {}\ntime {}\ntool {}\ntouched {}\nprevious {}\nresult {}\n",
+            self.operation,
+            self.time,
+            self.tool,
+            self.touched.join(" "),
+            self.previous.as_deref().unwrap_or("none"),
+            self.result
+        );
+        if let Some(key) = self.key {
+            text.push_str(&format!("key {}\n", hex::encode(key.to_bytes())));
+        }
+        text
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut text = self.signed_text();
+        if let Some(signature) = self.signature {
+            text.push_str(&format!("signature {}\n", hex::encode(signature.to_bytes())));
+        }
+        text.into_bytes()
+    }
+
+    fn parse(data: &[u8]) -> Result<Entry> {
+        let text = std::str::from_utf8(data)?;
+        let fields: BTreeMap<&str, &str> = text.lines().filter_map(|line| line.split_once(' ')).collect();
+        let field = |name: &str| fields.get(name).copied().ok_or_else(|| history_error(format!("entry has no {} line", name)));
+
+        let key = match fields.get("key") {
+            Some(key) => Some(VerifyingKey::from_bytes(&hex::decode(key)?.try_into().map_err(|_| history_error("key is not 32 bytes".into()))?)?),
+            None => None,
+        };
+        let signature = match fields.get("signature") {
+            Some(signature) => Some(Signature::from_slice(&hex::decode(signature)?)?),
+            None => None,
+        };
+        Ok(Entry {
+            operation: field("operation")?.to_string(),
+            time: field("time")?.parse()?,
+            tool: field("tool")?.to_string(),
+            touched: fields.get("touched").map(|t| t.split_whitespace().map(String::from).collect()).unwrap_or_default(),
+            previous: Some(field("previous")?.to_string()).filter(|p| p != "none"),
+            result: field("result")?.to_string(),
+            key,
+            signature,
+        })
+    }
+}
+
+fn without_history(png: &Png) -> Vec<&Chunk> {
+    png.chunks().iter().filter(|ch| ch.chunk_type().to_string() != HISTORY_CHUNK).collect()
+}
+
+/// sha-256 of the file without its history chunks
+pub fn content_hash(png: &Png) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(png.header());
+    without_history(png).iter().for_each(|ch| hasher.update(ch.as_bytes()));
+    hex::encode(hasher.finalize())
+}
+
+fn chunks_by_type(png: Option<&Png>) -> BTreeMap<String, Vec<&[u8]>> {
+    let mut map: BTreeMap<String, Vec<&[u8]>> = BTreeMap::new();
+    for ch in png.map(without_history).unwrap_or_default() {
+        map.entry(ch.chunk_type().to_string()).or_default().push(ch.data());
+    }
+    map
+}
+
+fn type_order(png: Option<&Png>) -> Vec<String> {
+    png.map(without_history).unwrap_or_default().iter().map(|ch| ch.chunk_type().to_string()).collect()
+}
+
+/// types whose chunks differ between the two files, or that moved when
+/// nothing else changed
+fn touched(before: Option<&Png>, after: &Png) -> Vec<String> {
+    let (old, new) = (chunks_by_type(before), chunks_by_type(Some(after)));
+    let mut touched: BTreeSet<String> = old.keys().chain(new.keys()).filter(|t| old.get(*t) != new.get(*t)).cloned().collect();
+    if touched.is_empty() {
+        let (old, new) = (type_order(before), type_order(Some(after)));
+        touched = old.into_iter().zip(new).filter(|(a, b)| a != b).map(|(a, _)| a).collect();
+    }
+    touched.into_iter().collect()
+}
+
+/// the entries of a png, oldest first
+pub fn entries(png: &Png) -> Result<Vec<Entry>> {
+    png.chunks().iter().filter(|ch| ch.chunk_type().bytes() == HISTORY_CHUNK.as_bytes()).map(|ch| Entry::parse(ch.data())).collect()
+}
+
+/// appends an entry to every file pngme writes, optionally signed
+#[derive(Default, Clone)]
+pub struct Recorder {
+    pub key: Option<SigningKey>,
+}
+
+impl Recorder {
+    /// adds the entry for the change from `before` to `after` to `after`;
+    /// returns false and records nothing when the content did not change
+    pub fn record_in(&self, before: Option<&Png>, after: &mut Png, operation: &str) -> Result<bool> {
+        let previous = before.map(content_hash);
+        let result = content_hash(after);
+        if previous.as_ref() == Some(&result) {
+            return Ok(false);
+        }
+
+        let mut entry = Entry {
+            operation: operation.to_string(),
+            time: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            tool: format!("pngme {}", env!("CARGO_PKG_VERSION")),
+            touched: touched(before, after),
+            previous,
+            result,
+            key: self.key.as_ref().map(SigningKey::verifying_key),
+            signature: None,
+        };
+        if let Some(key) = &self.key {
+            entry.signature = Some(key.sign(entry.signed_text().as_bytes()));
+        }
+        after.insert_before_end(Chunk::new(ChunkType::from_str(HISTORY_CHUNK)?, entry.to_bytes()));
+
+        Ok(true)
+    }
+
+    /// [`Recorder::record_in`] for a file already written to `path`, which
+    /// is rewritten with the entry; `before` is the file as it was read
+    pub fn record(&self, before: Option<&[u8]>, path: &Path, operation: &str) -> Result<bool> {
+        let bytes = fs::read(path)?;
+        if Format::detect(&bytes)? != Format::Png {
+            return Err(history_error(String::from("history is only kept in png files")));
+        }
+        let before = before.map(Png::try_from).transpose()?;
+        let mut after = Png::try_from(&bytes[..])?;
+        if !self.record_in(before.as_ref(), &mut after, operation)? {
+            return Ok(false);
+        }
+        fs::write(path, after.as_bytes())?;
+
+        Ok(true)
+    }
+}
+
+/// checks that every entry starts where the previous one ended, that the
+/// last one matches the file and that signatures hold; with `key` every entry
+/// has to be signed by it
+pub fn verify(png: &Png, key: Option<&VerifyingKey>) -> Result<Vec<Entry>> {
+    let entries = entries(png)?;
+    for (i, entry) in entries.iter().enumerate() {
+        match (entry.key, entry.signature) {
+            (Some(signer), Some(signature)) => signer
+                .verify(entry.signed_text().as_bytes(), &signature)
+                .map_err(|_| history_error(format!("entry {} has a bad signature", i + 1)))?,
+            (None, None) => {}
+            _ => return Err(history_error(format!("entry {} is half signed", i + 1))),
+        }
+        if key.is_some() && entry.key.as_ref() != key {
+            return Err(history_error(format!("entry {} is not signed by the given key", i + 1)));
+        }
+        if i > 0 && entry.previous.as_ref() != Some(&entries[i - 1].result) {
+            return Err(history_error(format!("a change between entries {} and {} was not recorded", i, i + 1)));
+        }
+    }
+    if let Some(last) = entries.last() {
+        if last.result != content_hash(png) {
+            return Err(history_error(format!("the file changed after entry {} without being recorded", entries.len())));
+        }
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::{ColorType, Image};
+    use crate::signing::generate_key;
+
+    fn sample() -> Png {
+        Png::from_image(&Image::solid(2, 2, [1, 2, 3, 255], ColorType::Rgb).unwrap()).unwrap()
+    }
+
+    fn edit(png: &Png, recorder: &Recorder, chunk_type: &str, data: &str) -> Png {
+        let mut after = Png::try_from(&png.as_bytes()[..]).unwrap();
+        after.insert_before_end(Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.as_bytes().to_vec()));
+        assert!(recorder.record_in(Some(png), &mut after, "encode").unwrap());
+        after
+    }
+
+    #[test]
+    fn test_chain_verifies() {
+        let key = generate_key().unwrap();
+        let recorder = Recorder { key: Some(key.clone()) };
+        let mut created = sample();
+        recorder.record_in(None, &mut created, "create").unwrap();
+        let png = edit(&edit(&created, &recorder, "ruSt", "one"), &recorder, "ruSt", "two");
+
+        let entries = verify(&png, Some(&key.verifying_key())).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].previous, None);
+        assert_eq!(entries[1].touched, ["ruSt"]);
+        assert!(entries[2].to_string().starts_with("encode on "));
+        assert!(verify(&png, Some(&generate_key().unwrap().verifying_key())).is_err());
+    }
+
+    #[test]
+    fn test_unrecorded_changes_break_the_chain() {
+        let recorder = Recorder::default();
+        let png = edit(&sample(), &recorder, "ruSt", "one");
+
+        let mut tampered = Png::try_from(&png.as_bytes()[..]).unwrap();
+        tampered.remove_chunk("ruSt").unwrap();
+        assert!(verify(&tampered, None).is_err());
+
+        // a later recorded write does not hide the gap
+        let later = edit(&tampered, &recorder, "teXt", "x");
+        assert!(verify(&later, None).unwrap_err().to_string().contains("between entries 1 and 2"));
+    }
+
+    #[test]
+    fn test_forged_entry_fails_signature() {
+        let recorder = Recorder { key: Some(generate_key().unwrap()) };
+        let png = edit(&sample(), &recorder, "ruSt", "one");
+        let data = png.chunk_by_type(HISTORY_CHUNK).unwrap().data();
+        let forged = String::from_utf8(data.to_vec()).unwrap().replace("operation encode", "operation remove");
+
+        let mut forged_png = Png::try_from(&png.as_bytes()[..]).unwrap();
+        forged_png.remove_chunk(HISTORY_CHUNK).unwrap();
+        forged_png.insert_before_end(Chunk::new(ChunkType::from_str(HISTORY_CHUNK).unwrap(), forged.into_bytes()));
+        assert!(verify(&forged_png, None).unwrap_err().to_string().contains("bad signature"));
+    }
+
+    #[test]
+    fn test_unchanged_content_is_not_recorded() {
+        let png = sample();
+        let mut same = Png::try_from(&png.as_bytes()[..]).unwrap();
+        assert!(!Recorder::default().record_in(Some(&png), &mut same, "reorder").unwrap());
+        assert_eq!(civil_date(20_379), (2025, 10, 18));
+    }
+
+    /// saves `png` again like an editor built on the png crate that keeps
+    /// the safe-to-copy chunks its decoder comes across, pixels re-encoded
+    fn resave(png: &Png) -> Png {
+        let bytes = png.as_bytes();
+        let mut decoder = ::png::StreamingDecoder::new();
+        let (mut rest, mut kept, mut seen) = (&bytes[..], Vec::new(), Vec::new());
+        loop {
+            let (consumed, decoded) = decoder.update(rest, &mut Vec::new()).unwrap();
+            rest = &rest[consumed..];
+            match decoded {
+                ::png::Decoded::ChunkComplete(_, chunk_type) => {
+                    let index = seen.iter().filter(|&&t| t == chunk_type).count();
+                    seen.push(chunk_type);
+                    let (ancillary, safe_to_copy) = (chunk_type.0[0].is_ascii_lowercase(), chunk_type.0[3].is_ascii_lowercase());
+                    if ancillary && safe_to_copy {
+                        let chunk = png.chunks().iter().filter(|ch| ch.chunk_type().bytes() == chunk_type.0).nth(index).unwrap();
+                        kept.push((chunk_type, chunk.data().to_vec()));
+                    }
+                }
+                ::png::Decoded::ImageEnd => break,
+                _ => {}
+            }
+        }
+
+        let mut reader = ::png::Decoder::new(&bytes[..]).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        let mut out = Vec::new();
+        let mut encoder = ::png::Encoder::new(&mut out, info.width, info.height);
+        encoder.set_color(info.color_type);
+        encoder.set_depth(info.bit_depth);
+        let mut writer = encoder.write_header().unwrap();
+        for (chunk_type, data) in kept {
+            writer.write_chunk(chunk_type, &data).unwrap();
+        }
+        writer.write_image_data(&pixels).unwrap();
+        writer.finish().unwrap();
+
+        Png::try_from(&out[..]).unwrap()
+    }
+
+    #[test]
+    fn test_history_survives_resave() {
+        let recorder = Recorder::default();
+        let mut created = sample();
+        recorder.record_in(None, &mut created, "create").unwrap();
+        let png = edit(&created, &recorder, "ruSt", "one");
+
+        let resaved = resave(&png);
+        let entries = entries(&resaved).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].touched, ["ruSt"]);
+        assert!(resaved.chunk_by_type("ruSt").is_some());
+    }
+}
@@ -0,0 +1,952 @@
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::limits::Limits;
+use crate::png::{Png, PngError, PngRef};
+use crate::{Error, Result};
+
+fn image_error(msg: &'static str) -> Error {
+    Box::new(PngError { msg })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ColorType {
+    Gray,
+    Rgb,
+    Indexed,
+    GrayAlpha,
+    Rgba,
+}
+
+impl ColorType {
+    /// value of the color type field in `IHDR`
+    pub fn code(&self) -> u8 {
+        match self {
+            ColorType::Gray => 0,
+            ColorType::Rgb => 2,
+            ColorType::Indexed => 3,
+            ColorType::GrayAlpha => 4,
+            ColorType::Rgba => 6,
+        }
+    }
+
+    pub fn from_code(code: u8) -> Result<ColorType> {
+        match code {
+            0 => Ok(ColorType::Gray),
+            2 => Ok(ColorType::Rgb),
+            3 => Ok(ColorType::Indexed),
+            4 => Ok(ColorType::GrayAlpha),
+            6 => Ok(ColorType::Rgba),
+            _ => Err(image_error("unknown color type")),
+        }
+    }
+
+    fn allowed_bit_depths(&self) -> &'static [u8] {
+        match self {
+            ColorType::Gray => &[1, 2, 4, 8, 16],
+            ColorType::Indexed => &[1, 2, 4, 8],
+            _ => &[8, 16],
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        match self {
+            ColorType::Gray | ColorType::Indexed => 1,
+            ColorType::GrayAlpha => 2,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ihdr {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: ColorType,
+    pub interlaced: bool,
+}
+
+impl Ihdr {
+    pub fn to_chunk(self) -> Result<Chunk> {
+        let data: Vec<u8> = self
+            .width
+            .to_be_bytes()
+            .into_iter()
+            .chain(self.height.to_be_bytes())
+            // compression and filter method are always 0
+            .chain([self.bit_depth, self.color_type.code(), 0, 0, self.interlaced as u8])
+            .collect();
+
+        Ok(Chunk::new(ChunkType::from_str("IHDR")?, data))
+    }
+
+    pub fn bits_per_pixel(&self) -> usize {
+        self.bit_depth as usize * self.color_type.channels()
+    }
+
+    /// bytes in a scanline `width` pixels wide, without the filter type byte
+    pub fn stride(&self, width: u32) -> usize {
+        (width as usize * self.bits_per_pixel()).div_ceil(8)
+    }
+
+    /// distance between corresponding bytes of neighbouring pixels used by filters
+    pub fn filter_bpp(&self) -> usize {
+        self.bits_per_pixel().div_ceil(8)
+    }
+
+    /// sizes of the reduced images stored one after another in `IDAT`: the
+    /// whole image, or the non empty adam7 passes of an interlaced one
+    pub fn passes(&self) -> Vec<(u32, u32)> {
+        if !self.interlaced {
+            return vec![(self.width, self.height)];
+        }
+
+        ADAM7
+            .iter()
+            .map(|&(x0, y0, dx, dy)| {
+                (
+                    self.width.saturating_sub(x0).div_ceil(dx),
+                    self.height.saturating_sub(y0).div_ceil(dy),
+                )
+            })
+            .filter(|&(w, h)| w > 0 && h > 0)
+            .collect()
+    }
+}
+
+/// first column, first row, column step and row step of each adam7 pass
+pub const ADAM7: [(u32, u32, u32, u32); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+impl TryFrom<&[u8]> for Ihdr {
+    type Error = Error;
+
+    fn try_from(data: &[u8]) -> Result<Self> {
+        if data.len() != 13 {
+            return Err(image_error("IHDR must be 13 bytes long"));
+        }
+        let ihdr = Ihdr {
+            width: u32::from_be_bytes(data[0..4].try_into()?),
+            height: u32::from_be_bytes(data[4..8].try_into()?),
+            bit_depth: data[8],
+            color_type: ColorType::from_code(data[9])?,
+            interlaced: data[12] == 1,
+        };
+
+        if ihdr.width == 0 || ihdr.height == 0 {
+            return Err(image_error("image has no pixels"));
+        }
+        if !ihdr.color_type.allowed_bit_depths().contains(&ihdr.bit_depth) {
+            return Err(image_error("bit depth not allowed for color type"));
+        }
+        if data[10] != 0 || data[11] != 0 || data[12] > 1 {
+            return Err(image_error("unknown compression, filter or interlace method"));
+        }
+
+        Ok(ihdr)
+    }
+}
+
+/// image data laid out as in the scanlines of a png, except that samples below
+/// 8 bits get a byte each; 16 bit samples take two big endian bytes. Indexed
+/// images carry their palette
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub color_type: ColorType,
+    pub bit_depth: u8,
+    pub palette: Option<Vec<[u8; 3]>>,
+    pub data: Vec<u8>,
+}
+
+fn luma(rgb: &[u8]) -> u8 {
+    ((rgb[0] as u32 * 299 + rgb[1] as u32 * 587 + rgb[2] as u32 * 114) / 1000) as u8
+}
+
+impl Image {
+    /// converts a buffer of 8 bit rgba pixels to the given color type; indexed
+    /// images are limited to 256 distinct colors and drop the alpha channel
+    pub fn from_rgba(width: u32, height: u32, rgba: &[u8], color_type: ColorType) -> Result<Image> {
+        if width == 0 || height == 0 || rgba.len() != width as usize * height as usize * 4 {
+            return Err(image_error("rgba buffer does not match image size"));
+        }
+
+        let mut palette: Vec<[u8; 3]> = Vec::new();
+        let mut data = Vec::with_capacity(rgba.len() / 4 * color_type.channels());
+        for px in rgba.chunks_exact(4) {
+            match color_type {
+                ColorType::Gray => data.push(luma(px)),
+                ColorType::GrayAlpha => data.extend_from_slice(&[luma(px), px[3]]),
+                ColorType::Rgb => data.extend_from_slice(&px[..3]),
+                ColorType::Rgba => data.extend_from_slice(px),
+                ColorType::Indexed => {
+                    let color = [px[0], px[1], px[2]];
+                    let idx = match palette.iter().position(|&c| c == color) {
+                        Some(idx) => idx,
+                        None if palette.len() < 256 => {
+                            palette.push(color);
+                            palette.len() - 1
+                        }
+                        None => return Err(image_error("too many colors for an indexed image")),
+                    };
+                    data.push(idx as u8);
+                }
+            }
+        }
+
+        Ok(Image {
+            width,
+            height,
+            color_type,
+            bit_depth: 8,
+            palette: (color_type == ColorType::Indexed).then_some(palette),
+            data,
+        })
+    }
+
+    pub fn solid(width: u32, height: u32, rgba: [u8; 4], color_type: ColorType) -> Result<Image> {
+        let pixels = rgba.repeat(width as usize * height as usize);
+        Image::from_rgba(width, height, &pixels, color_type)
+    }
+
+    /// opaque random pixels, the same seed always gives the same image
+    pub fn noise(width: u32, height: u32, seed: u64, color_type: ColorType) -> Result<Image> {
+        let mut state = seed.max(1);
+        let pixels: Vec<u8> = (0..width as usize * height as usize)
+            .flat_map(|_| {
+                // xorshift64*
+                state ^= state >> 12;
+                state ^= state << 25;
+                state ^= state >> 27;
+                let [r, g, b, ..] = state.wrapping_mul(0x2545_f491_4f6c_dd1d).to_be_bytes();
+                [r, g, b, 255]
+            })
+            .collect();
+        Image::from_rgba(width, height, &pixels, color_type)
+    }
+
+    /// bytes in a row of `data`
+    fn stride(&self) -> usize {
+        self.width as usize * self.color_type.channels() * if self.bit_depth == 16 { 2 } else { 1 }
+    }
+}
+
+fn scale_to_8_bits(value: u16, bits: u8) -> u8 {
+    match bits {
+        16 => (value >> 8) as u8,
+        8 => value as u8,
+        _ => (value as u32 * 255 / ((1 << bits) - 1)) as u8,
+    }
+}
+
+impl Ihdr {
+    /// column, row, column step and row step of every pass in `passes`
+    fn pass_origins(&self) -> Vec<(u32, u32, u32, u32)> {
+        if !self.interlaced {
+            return vec![(0, 0, 1, 1)];
+        }
+        ADAM7.iter().copied().filter(|&(x0, y0, _, _)| x0 < self.width && y0 < self.height).collect()
+    }
+
+    fn sample_bytes(&self) -> usize {
+        if self.bit_depth == 16 { 2 } else { 1 }
+    }
+}
+
+/// inflates, unfilters and deinterlaces the image data into an image of the
+/// same color type and bit depth
+fn decode_pixels(ihdr_data: &[u8], idat: impl Read, plte: Option<&[u8]>) -> Result<Image> {
+    let ihdr = Ihdr::try_from(ihdr_data)?;
+    let limits = Limits::default();
+    let pixel_bytes = ihdr.color_type.channels() * ihdr.sample_bytes();
+    let size = (ihdr.width as usize).saturating_mul(ihdr.height as usize).saturating_mul(pixel_bytes.max(4));
+    if size > limits.max_decompressed_size {
+        return Err(image_error("image is too large to decode"));
+    }
+    let passes = decode_passes(&ihdr, &limits.inflate_reader(idat)?)?;
+
+    let mut data = vec![0; ihdr.width as usize * ihdr.height as usize * pixel_bytes];
+    for ((pass, &(width, _)), (x0, y0, dx, dy)) in passes.iter().zip(&ihdr.passes()).zip(ihdr.pass_origins()) {
+        let samples = width as usize * ihdr.color_type.channels();
+        for (py, row) in pass.chunks_exact(ihdr.stride(width)).enumerate() {
+            let row = match ihdr.bit_depth {
+                8 | 16 => row.to_vec(),
+                bits => unpack_samples(row, bits, samples),
+            };
+            for (px, pixel) in row.chunks_exact(pixel_bytes).enumerate() {
+                let x = (x0 + px as u32 * dx) as usize;
+                let y = (y0 + py as u32 * dy) as usize;
+                let at = (y * ihdr.width as usize + x) * pixel_bytes;
+                data[at..at + pixel_bytes].copy_from_slice(pixel);
+            }
+        }
+    }
+
+    let palette = match (ihdr.color_type, plte) {
+        (ColorType::Indexed, Some(plte)) => Some(plte.chunks_exact(3).map(|rgb| [rgb[0], rgb[1], rgb[2]]).collect()),
+        (ColorType::Indexed, None) => return Err(image_error("indexed png has no PLTE chunk")),
+        _ => None,
+    };
+
+    Ok(Image {
+        width: ihdr.width,
+        height: ihdr.height,
+        color_type: ihdr.color_type,
+        bit_depth: ihdr.bit_depth,
+        palette,
+        data,
+    })
+}
+
+impl Image {
+    /// decodes the pixels of a png to 8 bit rgba, applying `PLTE` and `tRNS`
+    pub fn decode_rgba(png: &PngRef) -> Result<Image> {
+        png.decode_pixels()?.to_rgba(png.chunk_by_type("tRNS").map(|ch| ch.data()))
+    }
+
+    /// expands every color type and bit depth to 8 bit rgba; `trns` is the
+    /// data of the `tRNS` chunk, if any
+    pub fn to_rgba(&self, trns: Option<&[u8]>) -> Result<Image> {
+        let trns = trns.unwrap_or_default();
+        let key: Vec<u16> = trns.chunks_exact(2).map(|s| u16::from_be_bytes([s[0], s[1]])).collect();
+        let palette = self.palette.as_deref().unwrap_or_default();
+        let bits = self.bit_depth;
+        let samples: Vec<u16> = match bits {
+            16 => self.data.chunks_exact(2).map(|s| u16::from_be_bytes([s[0], s[1]])).collect(),
+            _ => self.data.iter().copied().map(u16::from).collect(),
+        };
+
+        let mut data = Vec::with_capacity(self.width as usize * self.height as usize * 4);
+        for px in samples.chunks_exact(self.color_type.channels()) {
+            let s = |i: usize| scale_to_8_bits(px[i], bits);
+            data.extend_from_slice(&match self.color_type {
+                ColorType::Gray => [s(0), s(0), s(0), if key == px { 0 } else { 255 }],
+                ColorType::Rgb => [s(0), s(1), s(2), if key == px { 0 } else { 255 }],
+                ColorType::GrayAlpha => [s(0), s(0), s(0), s(1)],
+                ColorType::Rgba => [s(0), s(1), s(2), s(3)],
+                ColorType::Indexed => {
+                    let idx = px[0] as usize;
+                    let [r, g, b] = *palette.get(idx).ok_or(PngError { msg: "palette index out of range" })?;
+                    [r, g, b, trns.get(idx).copied().unwrap_or(255)]
+                }
+            });
+        }
+
+        Ok(Image { width: self.width, height: self.height, color_type: ColorType::Rgba, bit_depth: 8, palette: None, data })
+    }
+
+    /// checks that the data, bit depth and palette describe a valid png image
+    fn validate(&self) -> Result<()> {
+        if !self.color_type.allowed_bit_depths().contains(&self.bit_depth) {
+            return Err(image_error("bit depth not allowed for color type"));
+        }
+        if self.width == 0 || self.height == 0 || self.data.len() != self.height as usize * self.stride() {
+            return Err(image_error("image data does not match image size"));
+        }
+        if self.bit_depth < 8 && self.data.iter().any(|&sample| sample >> self.bit_depth != 0) {
+            return Err(image_error("sample does not fit the bit depth"));
+        }
+        match (&self.palette, self.color_type) {
+            (Some(palette), ColorType::Indexed) => {
+                if palette.is_empty() || palette.len() > 1 << self.bit_depth {
+                    return Err(image_error("palette size does not fit the bit depth"));
+                }
+                if self.data.iter().any(|&idx| idx as usize >= palette.len()) {
+                    return Err(image_error("palette index out of range"));
+                }
+                Ok(())
+            }
+            (None, ColorType::Indexed) => Err(image_error("indexed image has no palette")),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl PngRef<'_> {
+    /// pixels at their native color type and bit depth, deinterlaced
+    pub fn decode_pixels(&self) -> Result<Image> {
+        let ihdr = self.chunk_by_type("IHDR").ok_or(PngError { msg: "png has no IHDR chunk" })?;
+        decode_pixels(ihdr.data(), self.idat_reader(), self.chunk_by_type("PLTE").map(|ch| ch.data()))
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// applies filter type `filter` to `row`, `prev` is the unfiltered previous row
+fn filter_row(filter: u8, row: &[u8], prev: &[u8], bpp: usize) -> Vec<u8> {
+    (0..row.len())
+        .map(|i| {
+            let a = if i >= bpp { row[i - bpp] } else { 0 };
+            let b = prev[i];
+            let c = if i >= bpp { prev[i - bpp] } else { 0 };
+            let predictor = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                _ => paeth(a, b, c),
+            };
+            row[i].wrapping_sub(predictor)
+        })
+        .collect()
+}
+
+/// how each scanline picks its filter type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterStrategy {
+    /// the same filter type for every scanline
+    Fixed(u8),
+    /// the type that minimizes the sum of absolute differences, the heuristic
+    /// suggested by the png specification
+    MinSumAbs,
+}
+
+impl FilterStrategy {
+    pub const ALL: [FilterStrategy; 6] = [
+        FilterStrategy::MinSumAbs,
+        FilterStrategy::Fixed(0),
+        FilterStrategy::Fixed(1),
+        FilterStrategy::Fixed(2),
+        FilterStrategy::Fixed(3),
+        FilterStrategy::Fixed(4),
+    ];
+}
+
+/// filters every scanline with [`FilterStrategy::MinSumAbs`]
+pub fn filter_scanlines(data: &[u8], stride: usize, bpp: usize) -> Vec<u8> {
+    filter_scanlines_with(data, stride, bpp, FilterStrategy::MinSumAbs)
+}
+
+pub fn filter_scanlines_with(data: &[u8], stride: usize, bpp: usize, strategy: FilterStrategy) -> Vec<u8> {
+    let zero_row = vec![0; stride];
+    let mut out = Vec::with_capacity(data.len() + data.len() / stride.max(1));
+    for (y, row) in data.chunks_exact(stride).enumerate() {
+        let prev = if y == 0 { &zero_row[..] } else { &data[(y - 1) * stride..y * stride] };
+        let (filter, filtered) = match strategy {
+            FilterStrategy::Fixed(filter) => (filter, filter_row(filter, row, prev, bpp)),
+            FilterStrategy::MinSumAbs => (0..5)
+                .map(|filter| (filter, filter_row(filter, row, prev, bpp)))
+                .min_by_key(|(_, filtered)| filtered.iter().map(|&b| (b as i8).unsigned_abs() as u32).sum::<u32>())
+                .unwrap(),
+        };
+        out.push(filter);
+        out.extend(filtered);
+    }
+
+    out
+}
+
+/// reverses [`filter_scanlines`] for the rows of a single image or pass
+pub fn unfilter_scanlines(data: &[u8], stride: usize, bpp: usize) -> Result<Vec<u8>> {
+    if !data.len().is_multiple_of(stride + 1) {
+        return Err(image_error("image data does not end on a scanline"));
+    }
+
+    let mut out: Vec<u8> = Vec::with_capacity(data.len() / (stride + 1) * stride);
+    for (y, line) in data.chunks_exact(stride + 1).enumerate() {
+        let start = y * stride;
+        for i in 0..stride {
+            let a = if i >= bpp { out[start + i - bpp] } else { 0 };
+            let b = if y > 0 { out[start - stride + i] } else { 0 };
+            let c = if y > 0 && i >= bpp { out[start - stride + i - bpp] } else { 0 };
+            let predictor = match line[0] {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err(image_error("unknown filter type")),
+            };
+            out.push(line[i + 1].wrapping_add(predictor));
+        }
+    }
+
+    Ok(out)
+}
+
+/// unfilters inflated `IDAT` data, returning the scanlines of every pass
+pub fn decode_passes(ihdr: &Ihdr, inflated: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut rest = inflated;
+    let mut passes = Vec::new();
+    for (width, height) in ihdr.passes() {
+        let len = (ihdr.stride(width) + 1) * height as usize;
+        if rest.len() < len {
+            return Err(image_error("image data is too short"));
+        }
+        passes.push(unfilter_scanlines(&rest[..len], ihdr.stride(width), ihdr.filter_bpp())?);
+        rest = &rest[len..];
+    }
+
+    Ok(passes)
+}
+
+/// filters the scanlines of every pass, ready to be compressed into `IDAT`
+pub fn encode_passes(ihdr: &Ihdr, passes: &[Vec<u8>]) -> Vec<u8> {
+    encode_passes_with(ihdr, passes, FilterStrategy::MinSumAbs)
+}
+
+pub fn encode_passes_with(ihdr: &Ihdr, passes: &[Vec<u8>], strategy: FilterStrategy) -> Vec<u8> {
+    ihdr.passes()
+        .iter()
+        .zip(passes)
+        .flat_map(|(&(width, _), pass)| filter_scanlines_with(pass, ihdr.stride(width), ihdr.filter_bpp(), strategy))
+        .collect()
+}
+
+/// samples of a scanline packed `bits` per sample, as used by bit depths below 8
+pub fn unpack_samples(row: &[u8], bits: u8, count: usize) -> Vec<u8> {
+    if bits == 8 {
+        return row[..count].to_vec();
+    }
+    let per_byte = 8 / bits as usize;
+    let mask = (1u16 << bits) as u8 - 1;
+    (0..count)
+        .map(|i| {
+            let shift = 8 - bits as usize * (i % per_byte + 1);
+            (row[i / per_byte] >> shift) & mask
+        })
+        .collect()
+}
+
+/// reverses [`unpack_samples`], the last byte is padded with zero bits
+pub fn pack_samples(samples: &[u8], bits: u8) -> Vec<u8> {
+    if bits == 8 {
+        return samples.to_vec();
+    }
+    let per_byte = 8 / bits as usize;
+    samples
+        .chunks(per_byte)
+        .map(|group| {
+            group
+                .iter()
+                .enumerate()
+                .fold(0, |byte, (i, &sample)| byte | sample << (8 - bits as usize * (i + 1)))
+        })
+        .collect()
+}
+
+pub fn zlib_compress(data: &[u8]) -> Result<Vec<u8>> {
+    zlib_compress_level(data, Compression::default().level())
+}
+
+/// compresses with a zlib level between 0 (store) and 9 (best)
+pub fn zlib_compress_level(data: &[u8], level: u32) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(level));
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+impl Png {
+    /// builds a complete png with `IHDR`, `PLTE` for indexed images, `IDAT` and `IEND`
+    pub fn from_image(image: &Image) -> Result<Png> {
+        let mut png = Png::from_chunks(vec![Chunk::new(ChunkType::from_str("IEND")?, Vec::new())]);
+        png.encode_pixels(image)?;
+        Ok(png)
+    }
+
+    /// pixels at their native color type and bit depth, deinterlaced
+    pub fn decode_pixels(&self) -> Result<Image> {
+        let ihdr = self.chunk_by_type("IHDR").ok_or(PngError { msg: "png has no IHDR chunk" })?;
+        let idat = self.image_data();
+        decode_pixels(ihdr.data(), &idat[..], self.chunk_by_type("PLTE").map(|ch| ch.data()))
+    }
+
+    /// replaces the pixels with `image`, rewriting `IHDR`, `PLTE` and `IDAT`;
+    /// the interlace method of the png is kept and other chunks are untouched,
+    /// except `tRNS`, `sBIT` and `bKGD` which are dropped when the color type
+    /// or bit depth changes
+    pub fn encode_pixels(&mut self, image: &Image) -> Result<()> {
+        image.validate()?;
+        let previous = self.chunk_by_type("IHDR").map(|ihdr| Ihdr::try_from(ihdr.data())).transpose()?;
+        let ihdr = Ihdr {
+            width: image.width,
+            height: image.height,
+            bit_depth: image.bit_depth,
+            color_type: image.color_type,
+            interlaced: previous.as_ref().is_some_and(|ihdr| ihdr.interlaced),
+        };
+
+        let pixel_bytes = ihdr.color_type.channels() * ihdr.sample_bytes();
+        let passes: Vec<Vec<u8>> = ihdr
+            .passes()
+            .into_iter()
+            .zip(ihdr.pass_origins())
+            .map(|((width, height), (x0, y0, dx, dy))| {
+                (0..height)
+                    .flat_map(|py| {
+                        let y = (y0 + py * dy) as usize;
+                        let row: Vec<u8> = (0..width)
+                            .flat_map(|px| {
+                                let at = (y * image.width as usize + (x0 + px * dx) as usize) * pixel_bytes;
+                                image.data[at..at + pixel_bytes].iter().copied()
+                            })
+                            .collect();
+                        match ihdr.bit_depth {
+                            8 | 16 => row,
+                            bits => pack_samples(&row, bits),
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        let idat = zlib_compress(&encode_passes(&ihdr, &passes))?;
+
+        if self.replace_chunk(ihdr.to_chunk()?).is_err() {
+            self.insert_chunk(0, ihdr.to_chunk()?);
+        }
+        // their layout depends on the color type and bit depth
+        if previous.is_none_or(|previous| previous.color_type != ihdr.color_type || previous.bit_depth != ihdr.bit_depth) {
+            self.retain_chunks(|ch| !matches!(&ch.chunk_type().bytes(), b"tRNS" | b"sBIT" | b"bKGD"));
+        }
+        let plte_type = ChunkType::from_str("PLTE")?;
+        match (&image.palette, image.color_type) {
+            (Some(palette), ColorType::Indexed) => {
+                let plte: Vec<u8> = palette.iter().flatten().copied().collect();
+                if self.replace_chunk(Chunk::new(plte_type, plte.clone())).is_err() {
+                    self.insert_chunk(1, Chunk::new(plte_type, plte));
+                }
+                // tRNS holds one alpha per palette entry at most and bKGD names an entry
+                if let Some(trns) = self.chunk_by_type("tRNS").filter(|trns| trns.data().len() > palette.len()) {
+                    let alphas = trns.data()[..palette.len()].to_vec();
+                    self.replace_chunk(Chunk::new(ChunkType::from_str("tRNS")?, alphas))?;
+                }
+                if self.chunk_by_type("bKGD").is_some_and(|bkgd| bkgd.data().first().is_none_or(|&idx| idx as usize >= palette.len())) {
+                    self.retain_chunks(|ch| ch.chunk_type().bytes() != *b"bKGD");
+                }
+            }
+            // a suggested palette is allowed for truecolor images only
+            (_, ColorType::Gray | ColorType::GrayAlpha) => self.retain_chunks(|ch| *ch.chunk_type() != plte_type),
+            _ => {}
+        }
+
+        if self.chunk_by_type("IDAT").is_some() {
+            return self.set_image_data(idat);
+        }
+        let end = self.chunks().iter().position(|ch| ch.chunk_type().bytes() == *b"IEND");
+        self.insert_chunk(end.unwrap_or(usize::MAX), Chunk::new(ChunkType::from_str("IDAT")?, idat));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::ZlibDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn test_filter_scanlines_round_trip() {
+        let image = Image::noise(13, 7, 42, ColorType::Rgb).unwrap();
+        let filtered = filter_scanlines(&image.data, image.stride(), 3);
+        assert_eq!(filtered.len(), image.data.len() + 7);
+        assert_eq!(unfilter_scanlines(&filtered, image.stride(), 3).unwrap(), image.data);
+    }
+
+    #[test]
+    fn test_unfilter_rejects_unknown_filter() {
+        assert!(unfilter_scanlines(&[5, 1, 2], 2, 1).is_err());
+        assert!(unfilter_scanlines(&[0, 1, 2, 0], 2, 1).is_err());
+    }
+
+    #[test]
+    fn test_ihdr_round_trip() {
+        let ihdr = Ihdr { width: 9, height: 5, bit_depth: 4, color_type: ColorType::Indexed, interlaced: true };
+        assert_eq!(Ihdr::try_from(ihdr.to_chunk().unwrap().data()).unwrap(), ihdr);
+        assert_eq!(ihdr.stride(9), 5);
+        assert_eq!(ihdr.filter_bpp(), 1);
+
+        let bad = Ihdr { bit_depth: 16, ..ihdr };
+        assert!(Ihdr::try_from(bad.to_chunk().unwrap().data()).is_err());
+    }
+
+    #[test]
+    fn test_adam7_passes() {
+        let ihdr = Ihdr { width: 9, height: 5, bit_depth: 8, color_type: ColorType::Gray, interlaced: true };
+        let passes = ihdr.passes();
+        assert_eq!(passes, [(2, 1), (1, 1), (3, 1), (2, 2), (5, 1), (4, 3), (9, 2)]);
+        assert_eq!(passes.iter().map(|(w, h)| w * h).sum::<u32>(), 45);
+
+        let tiny = Ihdr { width: 1, height: 1, ..ihdr };
+        assert_eq!(tiny.passes(), [(1, 1)]);
+    }
+
+    #[test]
+    fn test_pack_samples_round_trip() {
+        let samples = [1, 0, 3, 2, 2, 1, 0];
+        let packed = pack_samples(&samples, 2);
+        assert_eq!(packed, [0b0100_1110, 0b1001_0000]);
+        assert_eq!(unpack_samples(&packed, 2, 7), samples);
+    }
+
+    #[test]
+    fn test_solid_indexed_image() {
+        let image = Image::solid(4, 4, [10, 20, 30, 255], ColorType::Indexed).unwrap();
+        assert_eq!(image.palette, Some(vec![[10, 20, 30]]));
+        assert_eq!(image.data, vec![0; 16]);
+    }
+
+    #[test]
+    fn test_noise_is_reproducible() {
+        let first = Image::noise(8, 8, 7, ColorType::Rgba).unwrap();
+        assert_eq!(first, Image::noise(8, 8, 7, ColorType::Rgba).unwrap());
+        assert_ne!(first, Image::noise(8, 8, 8, ColorType::Rgba).unwrap());
+    }
+
+    #[test]
+    fn test_from_rgba_size_mismatch() {
+        assert!(Image::from_rgba(2, 2, &[0; 15], ColorType::Rgba).is_err());
+    }
+
+    #[test]
+    fn test_png_from_image() {
+        let image = Image::noise(5, 3, 1, ColorType::GrayAlpha).unwrap();
+        let png = Png::from_image(&image).unwrap();
+        let types: Vec<String> = png.chunks().iter().map(|ch| ch.chunk_type().to_string()).collect();
+        assert_eq!(types, ["IHDR", "IDAT", "IEND"]);
+        assert_eq!(png.chunks()[0].data(), [0, 0, 0, 5, 0, 0, 0, 3, 8, 4, 0, 0, 0]);
+
+        let mut inflated = Vec::new();
+        ZlibDecoder::new(png.chunks()[1].data()).read_to_end(&mut inflated).unwrap();
+        assert_eq!(unfilter_scanlines(&inflated, 10, 2).unwrap(), image.data);
+
+        let reparsed = Png::try_from(&png.as_bytes()[..]).unwrap();
+        assert_eq!(reparsed.as_bytes(), png.as_bytes());
+    }
+
+    #[test]
+    fn test_png_from_image_is_readable_by_other_decoders() {
+        for color_type in [ColorType::Gray, ColorType::Rgb, ColorType::GrayAlpha, ColorType::Rgba] {
+            let image = Image::noise(17, 9, 5, color_type).unwrap();
+            let bytes = Png::from_image(&image).unwrap().as_bytes();
+
+            let mut reader = png::Decoder::new(&bytes[..]).read_info().unwrap();
+            let mut buf = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut buf).unwrap();
+            assert_eq!(buf, image.data);
+        }
+
+        let image = Image::solid(3, 3, [1, 2, 3, 255], ColorType::Indexed).unwrap();
+        let bytes = Png::from_image(&image).unwrap().as_bytes();
+        let reader = png::Decoder::new(&bytes[..]).read_info().unwrap();
+        assert_eq!(reader.info().palette.as_deref(), Some(&[1, 2, 3][..]));
+    }
+
+    fn encode_with_png_crate(width: u32, color: png::ColorType, depth: png::BitDepth, palette: Option<(&[u8], &[u8])>, data: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, width, 1);
+        encoder.set_color(color);
+        encoder.set_depth(depth);
+        if let Some((plte, trns)) = palette {
+            encoder.set_palette(plte);
+            encoder.set_trns(trns);
+        }
+        encoder.write_header().unwrap().write_image_data(data).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_decode_rgba_16_bit() {
+        let data = [0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xff, 0xff, 0, 0, 0x01, 0x01];
+        let bytes = encode_with_png_crate(2, png::ColorType::Rgb, png::BitDepth::Sixteen, None, &data);
+
+        let image = Image::decode_rgba(&PngRef::try_from(&bytes[..]).unwrap()).unwrap();
+        assert_eq!(image.data, [0x12, 0x56, 0x9a, 255, 0xff, 0, 1, 255]);
+    }
+
+    #[test]
+    fn test_decode_rgba_indexed_with_transparency() {
+        let plte = [10, 20, 30, 40, 50, 60, 70, 80, 90, 100, 110, 120];
+        let bytes = encode_with_png_crate(3, png::ColorType::Indexed, png::BitDepth::Two, Some((&plte, &[0, 128])), &[0b00_01_11_00]);
+
+        let image = Image::decode_rgba(&PngRef::try_from(&bytes[..]).unwrap()).unwrap();
+        assert_eq!(image.data, [10, 20, 30, 0, 40, 50, 60, 128, 100, 110, 120, 255]);
+    }
+
+    #[test]
+    fn test_decode_rgba_interlaced() {
+        let image = Image::noise(11, 7, 3, ColorType::Rgba).unwrap();
+        let ihdr = Ihdr { width: 11, height: 7, bit_depth: 8, color_type: ColorType::Rgba, interlaced: true };
+        let passes: Vec<Vec<u8>> = ADAM7
+            .iter()
+            .map(|&(x0, y0, dx, dy)| {
+                (y0..7)
+                    .step_by(dy as usize)
+                    .flat_map(|y| (x0..11).step_by(dx as usize).map(move |x| ((y * 11 + x) * 4) as usize))
+                    .flat_map(|at| image.data[at..at + 4].to_vec())
+                    .collect()
+            })
+            .collect();
+        let idat = zlib_compress(&encode_passes(&ihdr, &passes)).unwrap();
+        let png = Png::from_chunks(vec![
+            ihdr.to_chunk().unwrap(),
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), idat),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+        ]);
+        let bytes = png.as_bytes();
+
+        let decoded = Image::decode_rgba(&PngRef::try_from(&bytes[..]).unwrap()).unwrap();
+        assert_eq!(decoded.data, image.data);
+    }
+
+    /// an image of every combination of color type and bit depth, with samples
+    /// spread over the whole range of the bit depth
+    fn all_kinds(width: u32, height: u32) -> Vec<Image> {
+        let mut state = 0x9e37_79b9_u32;
+        let mut next = move || {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) as u8
+        };
+        let mut images = Vec::new();
+        for color_type in [ColorType::Gray, ColorType::Rgb, ColorType::Indexed, ColorType::GrayAlpha, ColorType::Rgba] {
+            for &bit_depth in color_type.allowed_bit_depths() {
+                let samples = (width * height) as usize * color_type.channels() * if bit_depth == 16 { 2 } else { 1 };
+                let values = 1u16 << bit_depth.min(8);
+                let palette_len = values.min(5) as usize;
+                let values = if color_type == ColorType::Indexed { palette_len as u16 } else { values };
+                let data = (0..samples).map(|_| (next() as u16 % values) as u8).collect();
+                let palette = (color_type == ColorType::Indexed).then(|| (0..palette_len).map(|i| [i as u8 * 40, 7, 200]).collect());
+                images.push(Image { width, height, color_type, bit_depth, palette, data });
+            }
+        }
+        images
+    }
+
+    fn idat_png(ihdr: &Ihdr, palette: Option<&Vec<[u8; 3]>>, idat: Vec<u8>) -> Png {
+        let mut chunks = vec![ihdr.to_chunk().unwrap()];
+        if let Some(palette) = palette {
+            chunks.push(Chunk::new(ChunkType::from_str("PLTE").unwrap(), palette.iter().flatten().copied().collect()));
+        }
+        chunks.push(Chunk::new(ChunkType::from_str("IDAT").unwrap(), idat));
+        chunks.push(Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()));
+        Png::from_chunks(chunks)
+    }
+
+    #[test]
+    fn test_pixels_round_trip_every_kind() {
+        for interlaced in [false, true] {
+            for image in all_kinds(13, 11) {
+                let ihdr = Ihdr { width: 1, height: 1, bit_depth: 8, color_type: ColorType::Gray, interlaced };
+                let mut png = idat_png(&ihdr, None, Vec::new());
+                png.encode_pixels(&image).unwrap();
+
+                let header = Ihdr::try_from(png.chunk_by_type("IHDR").unwrap().data()).unwrap();
+                assert_eq!((header.bit_depth, header.interlaced), (image.bit_depth, interlaced));
+                assert_eq!(png.decode_pixels().unwrap(), image);
+            }
+        }
+    }
+
+    #[test]
+    fn test_pixels_match_other_decoders() {
+        for interlaced in [false, true] {
+            for image in all_kinds(9, 7) {
+                let ihdr = Ihdr { width: 1, height: 1, bit_depth: 8, color_type: ColorType::Gray, interlaced };
+                let mut png = idat_png(&ihdr, None, Vec::new());
+                png.encode_pixels(&image).unwrap();
+                let bytes = png.as_bytes();
+
+                let mut decoder = png::Decoder::new(&bytes[..]);
+                decoder.set_transformations(png::Transformations::IDENTITY);
+                let mut reader = decoder.read_info().unwrap();
+                let mut buf = vec![0; reader.output_buffer_size()];
+                reader.next_frame(&mut buf).unwrap();
+
+                let expected: Vec<u8> = image
+                    .data
+                    .chunks_exact(image.stride())
+                    .flat_map(|row| if image.bit_depth < 8 { pack_samples(row, image.bit_depth) } else { row.to_vec() })
+                    .collect();
+                assert_eq!(buf, expected, "{:?} at {} bits", image.color_type, image.bit_depth);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_pixels_every_filter_type() {
+        let image = &all_kinds(10, 6)[4];
+        assert_eq!((image.color_type, image.bit_depth), (ColorType::Gray, 16));
+        let ihdr = Ihdr { width: 10, height: 6, bit_depth: 16, color_type: ColorType::Gray, interlaced: false };
+        for filter in 0..5 {
+            let idat = zlib_compress(&encode_passes_with(&ihdr, std::slice::from_ref(&image.data), FilterStrategy::Fixed(filter))).unwrap();
+            assert_eq!(idat_png(&ihdr, None, idat).decode_pixels().unwrap(), *image);
+        }
+    }
+
+    #[test]
+    fn test_encode_pixels_keeps_other_chunks() {
+        let mut png = Png::from_image(&Image::solid(4, 4, [9, 9, 9, 255], ColorType::Rgb).unwrap()).unwrap();
+        let end = png.remove_chunk("IEND").unwrap();
+        png.append_chunk(Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"message".to_vec()));
+        png.append_chunk(end);
+
+        let mut image = png.decode_pixels().unwrap();
+        image.data[0] ^= 1;
+        png.encode_pixels(&image).unwrap();
+        assert_eq!(png.decode_pixels().unwrap(), image);
+        assert_eq!(png.chunk_by_type("ruSt").unwrap().data(), b"message");
+
+        let too_deep = Image { bit_depth: 2, data: vec![4; 16], ..Image::solid(4, 4, [0; 4], ColorType::Gray).unwrap() };
+        assert!(png.encode_pixels(&too_deep).is_err());
+    }
+
+    #[test]
+    fn test_encode_pixels_drops_color_chunks() {
+        let mut png = Png::from_image(&Image::solid(4, 4, [9, 9, 9, 255], ColorType::Rgb).unwrap()).unwrap();
+        for (chunk_type, data) in [("sBIT", vec![8; 3]), ("tRNS", vec![0; 6]), ("bKGD", vec![0; 6])] {
+            png.insert_chunk(1, Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data));
+        }
+
+        let same = png.decode_pixels().unwrap();
+        png.encode_pixels(&same).unwrap();
+        assert_eq!(png.chunk_by_type("tRNS").unwrap().data().len(), 6);
+
+        png.encode_pixels(&Image::solid(4, 4, [9, 9, 9, 255], ColorType::Gray).unwrap()).unwrap();
+        for chunk_type in ["tRNS", "sBIT", "bKGD"] {
+            assert!(png.chunk_by_type(chunk_type).is_none());
+        }
+        assert!(crate::commands::validate_bytes(&png.as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn test_encode_pixels_smaller_palette() {
+        let mut image = Image::noise(8, 8, 3, ColorType::Indexed).unwrap();
+        let mut png = Png::from_image(&image).unwrap();
+        let entries = image.palette.as_ref().unwrap().len();
+        png.insert_chunk(2, Chunk::new(ChunkType::from_str("tRNS").unwrap(), (0..entries as u8).collect()));
+        png.insert_chunk(2, Chunk::new(ChunkType::from_str("bKGD").unwrap(), vec![entries as u8 - 1]));
+
+        image.palette.as_mut().unwrap().truncate(2);
+        image.data.iter_mut().for_each(|idx| *idx %= 2);
+        png.encode_pixels(&image).unwrap();
+
+        assert_eq!(png.chunk_by_type("tRNS").unwrap().data(), [0, 1]);
+        assert!(png.chunk_by_type("bKGD").is_none());
+        assert_eq!(png.decode_pixels().unwrap(), image);
+    }
+}
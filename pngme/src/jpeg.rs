@@ -0,0 +1,256 @@
+use std::fmt;
+
+use crate::carrier::{carrier_error, Carrier, Format, Segment};
+use crate::{Error, Result};
+
+const SOI: [u8; 2] = [0xff, 0xd8];
+const SOS: u8 = 0xda;
+const EOI: u8 = 0xd9;
+const COM: u8 = 0xfe;
+const APP0: u8 = 0xe0;
+/// segment data is limited by its 16 bit length, which counts itself
+const MAX_SEGMENT_DATA: usize = u16::MAX as usize - 2;
+
+pub(crate) fn tag(marker: u8) -> String {
+    match marker {
+        APP0..=0xef => format!("APP{}", marker - APP0),
+        COM => String::from("COM"),
+        0xdb => String::from("DQT"),
+        0xc4 => String::from("DHT"),
+        0xdd => String::from("DRI"),
+        0xcc => String::from("DAC"),
+        0xc0..=0xcf => format!("SOF{}", marker - 0xc0),
+        _ => format!("FF{:02X}", marker),
+    }
+}
+
+fn marker(tag: &str) -> Option<u8> {
+    (0..=0xff).find(|&marker| self::tag(marker) == tag)
+}
+
+/// marker and data borrowed from the parsed file
+type RawSegment<'a> = (u8, &'a [u8]);
+
+/// splits a JPEG into the marker segments before the first scan and the rest
+/// of the file, which is kept as is
+pub(crate) fn parse_segments(bytes: &[u8]) -> Result<(Vec<RawSegment<'_>>, &[u8])> {
+    if !bytes.starts_with(&SOI) {
+        return Err(carrier_error("missing JPEG start of image"));
+    }
+
+    let mut segments = Vec::new();
+    let mut pos = SOI.len();
+    while pos < bytes.len() {
+        if bytes[pos] != 0xff {
+            return Err(carrier_error("expected a JPEG marker"));
+        }
+        let start = pos;
+        // markers may be preceded by any number of fill bytes
+        while bytes.get(pos) == Some(&0xff) {
+            pos += 1;
+        }
+        let marker = *bytes.get(pos).ok_or_else(|| carrier_error("truncated JPEG marker"))?;
+        if marker == SOS || marker == EOI {
+            return Ok((segments, &bytes[start..]));
+        }
+        if marker == 0x01 || (0xd0..=0xd7).contains(&marker) {
+            return Err(carrier_error("unexpected JPEG marker before the first scan"));
+        }
+
+        let len = bytes
+            .get(pos + 1..pos + 3)
+            .map(|len| u16::from_be_bytes([len[0], len[1]]) as usize)
+            .filter(|&len| len >= 2)
+            .ok_or_else(|| carrier_error("invalid JPEG segment length"))?;
+        let data = bytes
+            .get(pos + 3..pos + 1 + len)
+            .ok_or_else(|| carrier_error("JPEG segment is longer than the file"))?;
+        segments.push((marker, data));
+        pos += 1 + len;
+    }
+
+    Ok((segments, &[]))
+}
+
+/// a JPEG file, only the segments before the first scan are editable
+pub struct Jpeg {
+    segments: Vec<(u8, Vec<u8>)>,
+    /// scans and everything after them
+    rest: Vec<u8>,
+}
+
+impl TryFrom<&[u8]> for Jpeg {
+    type Error = Error;
+
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        let (segments, rest) = parse_segments(bytes)?;
+        Ok(Jpeg {
+            segments: segments.into_iter().map(|(marker, data)| (marker, data.to_vec())).collect(),
+            rest: rest.to_vec(),
+        })
+    }
+}
+
+impl fmt::Display for Jpeg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let out: Vec<_> = self.segments().iter().map(|seg| seg.to_string()).collect();
+
+        write!(f, "{}", out.join(" "))
+    }
+}
+
+impl Carrier for Jpeg {
+    fn format(&self) -> Format {
+        Format::Jpeg
+    }
+
+    fn segments(&self) -> Vec<Segment<'_>> {
+        self.segments
+            .iter()
+            .map(|(marker, data)| Segment { tag: tag(*marker), data })
+            .collect()
+    }
+
+    fn check_tag(&self, tag: &str) -> Result<()> {
+        match marker(tag) {
+            Some(COM) => Ok(()),
+            // APP0 to APP2 hold JFIF, Exif and ICC profiles, readers parse them
+            Some(marker @ 0xe3..=0xef) if marker != 0xee => Ok(()),
+            _ => Err(carrier_error("messages go in COM or APP3 to APP15 but APP14 segments")),
+        }
+    }
+
+    fn free_tag(&self) -> Option<String> {
+        (0xe3..=0xef)
+            .rev()
+            .filter(|&marker| marker != 0xee)
+            .find(|&marker| self.segments.iter().all(|(m, _)| *m != marker))
+            .map(tag)
+    }
+
+    fn insert(&mut self, tag: &str, data: Vec<u8>) -> Result<()> {
+        let marker = marker(tag).ok_or_else(|| carrier_error("unknown JPEG segment"))?;
+        if data.len() > MAX_SEGMENT_DATA {
+            return Err(carrier_error("data does not fit in a JPEG segment"));
+        }
+        // keep metadata together, ahead of the tables and frame header
+        let idx = self
+            .segments
+            .iter()
+            .position(|(m, _)| !(APP0..=0xef).contains(m) && *m != COM)
+            .unwrap_or(self.segments.len());
+        self.segments.insert(idx, (marker, data));
+
+        Ok(())
+    }
+
+    fn remove(&mut self, tag: &str) -> Result<Vec<u8>> {
+        let idx = self
+            .segments
+            .iter()
+            .position(|(marker, _)| self::tag(*marker) == tag)
+            .ok_or_else(|| carrier_error("segment not found"))?;
+
+        Ok(self.segments.remove(idx).1)
+    }
+
+    fn retain(&mut self, f: &mut dyn FnMut(&Segment) -> bool) {
+        self.segments.retain(|(marker, data)| f(&Segment { tag: tag(*marker), data }));
+    }
+
+    fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = SOI.to_vec();
+        for (marker, data) in &self.segments {
+            bytes.extend_from_slice(&[0xff, *marker]);
+            bytes.extend_from_slice(&(data.len() as u16 + 2).to_be_bytes());
+            bytes.extend_from_slice(data);
+        }
+        bytes.extend_from_slice(&self.rest);
+
+        bytes
+    }
+
+    /// segment tags say little about where a message is in a JPEG, so the
+    /// signature covers the whole file as re-serialised here, which drops
+    /// fill bytes and does not depend on how the file was written
+    fn signed_location(&self, tag: &str) -> Vec<u8> {
+        [tag.as_bytes(), &self.as_bytes()].concat()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::carrier;
+    use crate::commands;
+    use crate::payload::Options;
+    use ed25519_dalek::SigningKey;
+
+    fn sample() -> Vec<u8> {
+        let mut bytes = SOI.to_vec();
+        bytes.extend_from_slice(&[0xff, 0xe0, 0, 7]);
+        bytes.extend_from_slice(b"JFIF\0");
+        bytes.extend_from_slice(&[0xff, 0xdb, 0, 3, 9]);
+        bytes.extend_from_slice(&[0xff, SOS, 0, 2, 0x12, 0xff, 0x00, 0x34, 0xff, EOI]);
+        bytes
+    }
+
+    #[test]
+    fn test_parse_and_serialize() {
+        let jpeg = Jpeg::try_from(&sample()[..]).unwrap();
+        let tags: Vec<_> = jpeg.segments().into_iter().map(|seg| seg.tag).collect();
+        assert_eq!(tags, vec!["APP0", "DQT"]);
+        assert_eq!(jpeg.as_bytes(), sample());
+    }
+
+    #[test]
+    fn test_insert_keeps_metadata_first() {
+        let mut jpeg = Jpeg::try_from(&sample()[..]).unwrap();
+        let tag = jpeg.free_tag().unwrap();
+        assert_eq!(tag, "APP15");
+        jpeg.insert(&tag, b"hidden".to_vec()).unwrap();
+        jpeg.insert("COM", b"note".to_vec()).unwrap();
+
+        let mut reparsed = Jpeg::try_from(&jpeg.as_bytes()[..]).unwrap();
+        let tags: Vec<_> = reparsed.segments().into_iter().map(|seg| seg.tag).collect();
+        assert_eq!(tags, vec!["APP0", "APP15", "COM", "DQT"]);
+        assert_eq!(reparsed.remove("APP15").unwrap(), b"hidden");
+    }
+
+    #[test]
+    fn test_check_tag() {
+        let jpeg = Jpeg::try_from(&sample()[..]).unwrap();
+        assert!(jpeg.check_tag("COM").is_ok());
+        assert!(jpeg.check_tag("APP9").is_ok());
+        assert!(jpeg.check_tag("APP1").is_err());
+        assert!(jpeg.check_tag("DQT").is_err());
+    }
+
+    #[test]
+    fn test_signature_survives_resave() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let options = Options { sign: Some(key.clone()), ..Options::default() };
+        let mut jpeg = Jpeg::try_from(&sample()[..]).unwrap();
+        let tag = commands::encode_into(&mut jpeg, None, b"signed", &options).unwrap();
+        let written = jpeg.as_bytes();
+
+        // another writer pads the table marker with fill bytes
+        let dqt = written.windows(2).position(|pair| pair == [0xff, 0xdb]).unwrap();
+        let resaved = [&written[..dqt], &[0xff, 0xff], &written[dqt..]].concat();
+        let (_, segments) = carrier::segments(&resaved).unwrap();
+        let (payload, _) = commands::find_payload(Format::Jpeg, &segments, None).unwrap();
+        let location = commands::signed_location(&resaved, &tag).unwrap();
+        assert!(payload.verify(&key.verifying_key(), &location).is_ok());
+
+        // the tables are covered too
+        let mut tampered = written.clone();
+        tampered[dqt + 4] ^= 1;
+        assert!(payload.verify(&key.verifying_key(), &commands::signed_location(&tampered, &tag).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_oversized_segment() {
+        let mut jpeg = Jpeg::try_from(&sample()[..]).unwrap();
+        assert!(jpeg.insert("COM", vec![0; MAX_SEGMENT_DATA + 1]).is_err());
+    }
+}
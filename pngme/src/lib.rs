@@ -0,0 +1,34 @@
+#[cfg(feature = "async")]
+pub mod async_io;
+pub mod batch;
+pub mod carrier;
+pub mod chunk;
+pub mod chunk_type;
+pub mod commands;
+pub mod ffi;
+pub mod history;
+pub mod image;
+pub mod jpeg;
+pub mod limits;
+pub mod lsb;
+pub mod optimize;
+pub mod palette;
+pub mod payload;
+pub mod png;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod reorder;
+pub mod riff;
+pub mod shell;
+pub mod signing;
+pub mod view;
+pub mod watermark;
+
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// parses untrusted bytes within the given limits; meant for services handling
+/// uploads and for fuzzers, it never allocates more than the limits allow
+pub fn parse_with_limits<'a>(data: &'a [u8], limits: &limits::Limits) -> Result<png::PngRef<'a>> {
+    png::PngRef::parse_with_limits(data, limits)
+}
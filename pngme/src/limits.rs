@@ -0,0 +1,111 @@
+use std::io::{self, Read};
+
+use flate2::read::ZlibDecoder;
+
+use crate::png::PngError;
+use crate::{Error, Result};
+
+/// largest chunk length allowed by the png specification, 2^31 - 1
+pub const MAX_CHUNK_SIZE: u32 = i32::MAX as u32;
+
+fn limit_error(msg: &'static str) -> Error {
+    Box::new(PngError { msg })
+}
+
+/// bounds applied while parsing untrusted pngs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub max_chunk_size: u32,
+    pub max_total_size: usize,
+    pub max_chunks: usize,
+    /// applies to each zlib stream: `IDAT`, `iCCP`, `zTXt` and compressed `iTXt`
+    pub max_decompressed_size: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_chunk_size: MAX_CHUNK_SIZE,
+            max_total_size: 1 << 30,
+            max_chunks: 1 << 20,
+            max_decompressed_size: 1 << 30,
+        }
+    }
+}
+
+impl Limits {
+    pub fn check_total_size(&self, size: usize) -> Result<()> {
+        if size > self.max_total_size {
+            return Err(limit_error("file exceeds the maximum total size"));
+        }
+        Ok(())
+    }
+
+    pub fn check_chunk(&self, index: usize, length: u32) -> Result<()> {
+        if index >= self.max_chunks {
+            return Err(limit_error("file exceeds the maximum chunk count"));
+        }
+        if length > self.max_chunk_size.min(MAX_CHUNK_SIZE) {
+            return Err(limit_error("chunk exceeds the maximum chunk size"));
+        }
+        Ok(())
+    }
+
+    /// inflates a zlib stream, failing as soon as the output would exceed
+    /// `max_decompressed_size` instead of allocating it
+    pub fn inflate(&self, data: &[u8]) -> Result<Vec<u8>> {
+        self.inflate_reader(data)
+    }
+
+    /// [`Limits::inflate`] for streams split over several chunks
+    pub fn inflate_reader<R: Read>(&self, data: R) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        self.inflate_into(data, &mut out)?;
+        Ok(out)
+    }
+
+    /// measures the inflated size of a zlib stream without keeping the output
+    pub fn check_inflated_size<R: Read>(&self, data: R) -> Result<u64> {
+        self.inflate_into(data, &mut io::sink())
+    }
+
+    fn inflate_into<R: Read, W: io::Write>(&self, data: R, out: &mut W) -> Result<u64> {
+        let limit = self.max_decompressed_size as u64;
+        let written = io::copy(&mut ZlibDecoder::new(data).take(limit + 1), out)?;
+        if written > limit {
+            return Err(limit_error("zlib stream exceeds the maximum decompressed size"));
+        }
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::zlib_compress;
+
+    #[test]
+    fn test_check_chunk() {
+        let limits = Limits { max_chunk_size: 10, max_chunks: 2, ..Limits::default() };
+        assert!(limits.check_chunk(0, 10).is_ok());
+        assert!(limits.check_chunk(0, 11).is_err());
+        assert!(limits.check_chunk(2, 0).is_err());
+    }
+
+    #[test]
+    fn test_spec_chunk_limit_always_applies() {
+        let limits = Limits { max_chunk_size: u32::MAX, ..Limits::default() };
+        assert!(limits.check_chunk(0, MAX_CHUNK_SIZE).is_ok());
+        assert!(limits.check_chunk(0, MAX_CHUNK_SIZE + 1).is_err());
+    }
+
+    #[test]
+    fn test_inflate_stops_at_limit() {
+        let bomb = zlib_compress(&vec![0; 1 << 20]).unwrap();
+        let limits = Limits { max_decompressed_size: 1000, ..Limits::default() };
+
+        assert!(limits.inflate(&bomb).is_err());
+        assert!(limits.check_inflated_size(&bomb[..]).is_err());
+        assert_eq!(Limits::default().inflate(&bomb).unwrap().len(), 1 << 20);
+    }
+}
@@ -0,0 +1,425 @@
+use std::fmt;
+
+use sha2::{Digest, Sha256};
+
+use crate::image::{ColorType, Image};
+use crate::png::PngError;
+use crate::{Error, Result};
+
+// Messages are hidden in the least significant bits of the color samples.
+// A passphrase seeds a keystream that shuffles the samples, so the bits are
+// scattered over the image instead of filling it from the top. A sample whose
+// bit has to change is moved up or down by one (LSB matching) rather than
+// having its bit overwritten, which keeps the pairs of values 2i and 2i + 1
+// from evening out, the trace picked up by the chi-square attack. The message
+// is written with matrix embedding: each group of 2^k - 1 samples carries k
+// bits as the syndrome of a Hamming code, with at most one change per group.
+
+/// magic, hamming parameter and message length, written with k = 1
+const HEADER_BITS: usize = 56;
+const MAGIC: u16 = 0x704d;
+const MAX_K: u32 = 12;
+/// pairs of values expected to occur fewer times are left out of the chi-square sum
+const MIN_EXPECTED: f64 = 5.0;
+
+fn lsb_error(msg: &'static str) -> Error {
+    Box::new(PngError { msg })
+}
+
+/// what an embedding costs, known before anything is written
+#[derive(Debug, Clone, PartialEq)]
+pub struct Estimate {
+    /// bytes available with one bit per sample
+    pub capacity: usize,
+    pub used: usize,
+    /// bits per group of 2^k - 1 samples
+    pub k: u32,
+    pub changes: usize,
+    pub samples: usize,
+    /// result of [`chi_square`] on the image before embedding
+    pub cover_chi_square: f64,
+    /// result of [`chi_square`] on the image after embedding
+    pub chi_square: f64,
+}
+
+impl Estimate {
+    /// rough verdict from the share of changed samples, LSB matching below
+    /// one change in a hundred samples is hard to tell from sensor noise;
+    /// covers that already look embedded, like noise, only count the rise
+    pub fn detectability(&self) -> &'static str {
+        let rate = self.changes as f64 / self.samples.max(1) as f64;
+        match rate {
+            _ if self.chi_square - self.cover_chi_square > 0.5 => "high",
+            rate if rate < 0.01 => "low",
+            rate if rate < 0.05 => "moderate",
+            _ => "high",
+        }
+    }
+}
+
+impl fmt::Display for Estimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} bytes, k = {}, {} of {} samples changed ({:.3}%), chi-square {:.3} -> {:.3}, detectability {}",
+            self.used,
+            self.capacity,
+            self.k,
+            self.changes,
+            self.samples,
+            self.changes as f64 * 100.0 / self.samples.max(1) as f64,
+            self.cover_chi_square,
+            self.chi_square,
+            self.detectability(),
+        )
+    }
+}
+
+/// sha-256 in counter mode, keyed with the passphrase
+pub(crate) struct KeyStream {
+    key: [u8; 32],
+    counter: u64,
+    block: [u8; 32],
+    pos: usize,
+}
+
+impl KeyStream {
+    pub(crate) fn new(passphrase: &[u8]) -> KeyStream {
+        let key = Sha256::new().chain_update(b"pngme lsb\0").chain_update(passphrase).finalize().into();
+        KeyStream { key, counter: 0, block: [0; 32], pos: 32 }
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        if self.pos == self.block.len() {
+            self.block = Sha256::new().chain_update(self.key).chain_update(self.counter.to_be_bytes()).finalize().into();
+            self.counter += 1;
+            self.pos = 0;
+        }
+        let bytes = self.block[self.pos..self.pos + 8].try_into().unwrap_or_default();
+        self.pos += 8;
+        u64::from_be_bytes(bytes)
+    }
+
+    /// number below `n`, the modulo bias is negligible for image sizes
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+/// samples in a keyed order, shuffled only as far as they are taken
+struct Walk {
+    order: Vec<usize>,
+    taken: usize,
+    stream: KeyStream,
+}
+
+impl Walk {
+    fn take(&mut self, n: usize) -> Result<&[usize]> {
+        let end = self.taken + n;
+        if end > self.order.len() {
+            return Err(lsb_error("message does not fit in the image"));
+        }
+        for i in self.taken..end {
+            let j = i + self.stream.below(self.order.len() - i);
+            self.order.swap(i, j);
+        }
+        self.taken = end;
+        Ok(&self.order[end - n..end])
+    }
+}
+
+/// the samples messages go in: every color sample of 8 and 16 bit images,
+/// alpha excluded
+fn cover(image: &Image) -> Result<Vec<usize>> {
+    if image.color_type == ColorType::Indexed || image.bit_depth < 8 {
+        return Err(lsb_error("lsb mode needs an 8 or 16 bit image that is not indexed"));
+    }
+    let channels = image.color_type.channels();
+    let colors = match image.color_type {
+        ColorType::GrayAlpha | ColorType::Rgba => channels - 1,
+        _ => channels,
+    };
+    let pixels = image.width as usize * image.height as usize;
+    Ok((0..pixels).flat_map(|px| (0..colors).map(move |c| px * channels + c)).collect())
+}
+
+fn value(image: &Image, sample: usize) -> u16 {
+    match image.bit_depth {
+        16 => u16::from_be_bytes([image.data[sample * 2], image.data[sample * 2 + 1]]),
+        _ => image.data[sample] as u16,
+    }
+}
+
+fn bit(image: &Image, sample: usize) -> u8 {
+    (value(image, sample) & 1) as u8
+}
+
+/// flips the lowest bit by adding or subtracting one
+fn step(image: &mut Image, sample: usize, up: bool) {
+    let max = if image.bit_depth == 16 { u16::MAX } else { u8::MAX as u16 };
+    let value = value(image, sample);
+    let value = if (up && value < max) || value == 0 { value + 1 } else { value - 1 };
+    match image.bit_depth {
+        16 => image.data[sample * 2..sample * 2 + 2].copy_from_slice(&value.to_be_bytes()),
+        _ => image.data[sample] = value as u8,
+    }
+}
+
+fn bits_of(value: u64, count: usize) -> impl Iterator<Item = u8> {
+    (0..count).rev().map(move |i| (value >> i & 1) as u8)
+}
+
+/// xor of the 1 based positions of the set bits, the syndrome of the hamming code
+fn syndrome(image: &Image, group: &[usize]) -> usize {
+    group.iter().enumerate().filter(|(_, &s)| bit(image, s) == 1).fold(0, |acc, (i, _)| acc ^ (i + 1))
+}
+
+/// bytes that fit in the image with one bit per sample
+pub fn capacity(image: &Image) -> Result<usize> {
+    Ok(cover(image)?.len().saturating_sub(HEADER_BITS) / 8)
+}
+
+/// largest k whose groups still fit the message in `available` samples
+fn choose_k(bits: usize, available: usize) -> Option<u32> {
+    (1..=MAX_K).rev().find(|&k| bits.div_ceil(k as usize) * ((1 << k) - 1) <= available)
+}
+
+/// hides `data` in the samples picked by `passphrase`
+pub fn embed(image: &mut Image, data: &[u8], passphrase: &[u8]) -> Result<Estimate> {
+    let indices = cover(image)?;
+    let samples = indices.len();
+    let capacity = samples.saturating_sub(HEADER_BITS) / 8;
+    let cover_chi_square = chi_square(image)?;
+    let len = u32::try_from(data.len()).map_err(|_| lsb_error("message is too long"))?;
+    let k = choose_k(data.len() * 8, samples.saturating_sub(HEADER_BITS))
+        .ok_or_else(|| lsb_error("message does not fit in the image"))?;
+
+    let mut walk = Walk { order: indices, taken: 0, stream: KeyStream::new(passphrase) };
+    // extraction never needs the directions, they only have to look random
+    let mut directions = KeyStream::new(&[passphrase, b"\0steps"].concat());
+    let mut changes = 0;
+
+    let header = bits_of(MAGIC as u64, 16).chain(bits_of(k as u64, 8)).chain(bits_of(len as u64, 32));
+    let positions = walk.take(HEADER_BITS)?.to_vec();
+    for (bit, sample) in header.zip(positions) {
+        if self::bit(image, sample) != bit {
+            step(image, sample, directions.next_u64() & 1 == 1);
+            changes += 1;
+        }
+    }
+
+    let mut bits = data.iter().flat_map(|&byte| bits_of(byte as u64, 8));
+    let group_len = (1 << k) - 1;
+    for _ in 0..(data.len() * 8).div_ceil(k as usize) {
+        let value = (0..k).fold(0, |acc, _| acc << 1 | bits.next().unwrap_or(0) as usize);
+        let group = walk.take(group_len)?.to_vec();
+        let flip = syndrome(image, &group) ^ value;
+        if flip != 0 {
+            step(image, group[flip - 1], directions.next_u64() & 1 == 1);
+            changes += 1;
+        }
+    }
+
+    let chi_square = chi_square(image)?;
+    Ok(Estimate { capacity, used: data.len(), k, changes, samples, cover_chi_square, chi_square })
+}
+
+/// reverses [`embed`], fails when `passphrase` does not lead to a message
+pub fn extract(image: &Image, passphrase: &[u8]) -> Result<Vec<u8>> {
+    let indices = cover(image)?;
+    let samples = indices.len();
+    let mut walk = Walk { order: indices, taken: 0, stream: KeyStream::new(passphrase) };
+
+    let header = walk.take(HEADER_BITS).map_err(|_| lsb_error("image is too small for lsb mode"))?;
+    let header = header.iter().fold(0u64, |acc, &s| acc << 1 | bit(image, s) as u64);
+    let (magic, k, len) = (header >> 40, (header >> 32 & 0xff) as u32, (header & 0xffff_ffff) as usize);
+    if magic != MAGIC as u64 || k == 0 || k > MAX_K {
+        return Err(lsb_error("no message found for this passphrase"));
+    }
+
+    let group_len = (1 << k) - 1;
+    let groups = len.saturating_mul(8).div_ceil(k as usize);
+    if groups.saturating_mul(group_len) > samples - HEADER_BITS {
+        return Err(lsb_error("no message found for this passphrase"));
+    }
+    let mut bits = Vec::with_capacity(groups * k as usize);
+    for _ in 0..groups {
+        let value = syndrome(image, walk.take(group_len)?);
+        bits.extend(bits_of(value as u64, k as usize));
+    }
+
+    Ok(bits.chunks(8).take(len).map(|byte| byte.iter().fold(0, |acc, &b| acc << 1 | b)).collect())
+}
+
+/// chi-square statistic and degrees of freedom of the pairs of values 2i and 2i + 1
+fn pairs_statistic(histogram: &[u64; 256]) -> Option<(f64, f64)> {
+    let (mut statistic, mut categories) = (0.0, 0);
+    for pair in histogram.chunks_exact(2) {
+        let expected = (pair[0] + pair[1]) as f64 / 2.0;
+        if expected >= MIN_EXPECTED {
+            statistic += (pair[0] as f64 - expected).powi(2) / expected;
+            categories += 1;
+        }
+    }
+    (categories >= 2).then(|| (statistic, (categories - 1) as f64))
+}
+
+/// probability that the low bits of the color samples were replaced by
+/// message bits, after the pairs of values test of Westfeld and Pfitzmann.
+/// The test runs on the first tenth, two tenths and so on of the samples in
+/// scan order and the highest result is returned, so a message written from
+/// the top of the image is found even when it fills only part of it
+pub fn chi_square(image: &Image) -> Result<f64> {
+    let samples = cover(image)?;
+    let low_byte = if image.bit_depth == 16 { 1 } else { 0 };
+    let mut histogram = [0u64; 256];
+    let mut highest: f64 = 0.0;
+    for tenth in 1..=10 {
+        let (start, end) = (samples.len() * (tenth - 1) / 10, samples.len() * tenth / 10);
+        for &sample in &samples[start..end] {
+            histogram[image.data[sample * (1 + low_byte) + low_byte] as usize] += 1;
+        }
+        if let Some((statistic, dof)) = pairs_statistic(&histogram) {
+            highest = highest.max(1.0 - regularized_gamma_p(dof / 2.0, statistic / 2.0));
+        }
+    }
+
+    Ok(highest)
+}
+
+/// natural log of the gamma function, lanczos approximation for x >= 0.5
+fn ln_gamma(x: f64) -> f64 {
+    const G: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    let x = x - 1.0;
+    let sum = G[1..].iter().enumerate().fold(G[0], |acc, (i, g)| acc + g / (x + i as f64 + 1.0));
+    let t = x + 7.5;
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + sum.ln()
+}
+
+/// lower regularized incomplete gamma function P(a, x), the chi-square cdf
+/// with 2a degrees of freedom at 2x
+fn regularized_gamma_p(a: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    let front = (-x + a * x.ln() - ln_gamma(a)).exp();
+    if x < a + 1.0 {
+        let (mut term, mut sum, mut n) = (1.0 / a, 1.0 / a, a);
+        while term.abs() > sum.abs() * 1e-14 {
+            n += 1.0;
+            term *= x / n;
+            sum += term;
+        }
+        return (sum * front).min(1.0);
+    }
+
+    // continued fraction for the upper function, evaluated with lentz's method
+    let tiny = 1e-300;
+    let mut b = x + 1.0 - a;
+    let (mut c, mut d) = (1.0 / tiny, 1.0 / b);
+    let mut h = d;
+    for i in 1..1000 {
+        let an = -(i as f64) * (i as f64 - a);
+        b += 2.0;
+        d = an * d + b;
+        d = if d.abs() < tiny { tiny } else { d };
+        c = b + an / c;
+        c = if c.abs() < tiny { tiny } else { c };
+        d = 1.0 / d;
+        let delta = d * c;
+        h *= delta;
+        if (delta - 1.0).abs() < 1e-14 {
+            break;
+        }
+    }
+    (1.0 - front * h).max(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// smooth gradient where, like in photographs, the two values of a pair
+    /// 2i and 2i + 1 are not equally frequent
+    fn photo(width: u32, height: u32) -> Image {
+        let mut state = 12345u32;
+        let rgba: Vec<u8> = (0..width * height)
+            .flat_map(|i| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                let odd = (state >> 16).is_multiple_of(4) as u8;
+                let (x, y) = (i % width, i / width);
+                let v = ((x + y) * 254 / (width + height)) as u8 & !1;
+                [v | odd, (v / 2) & !1 | odd, (254 - v) | odd, 255]
+            })
+            .collect();
+        Image::from_rgba(width, height, &rgba, ColorType::Rgba).unwrap()
+    }
+
+    fn message(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 256) as u8 ^ 0x5a).collect()
+    }
+
+    #[test]
+    fn test_embed_extract_round_trip() {
+        let mut image = photo(40, 30);
+        let estimate = embed(&mut image, b"meet at noon", b"secret").unwrap();
+        assert_eq!(extract(&image, b"secret").unwrap(), b"meet at noon");
+        assert!(extract(&image, b"wrong").is_err());
+        assert!(estimate.k > 1);
+        assert!(estimate.changes < 12 * 8 / 2);
+    }
+
+    #[test]
+    fn test_changes_are_plus_or_minus_one() {
+        let original = photo(20, 20);
+        let mut image = original.clone();
+        embed(&mut image, &message(100), b"key").unwrap();
+        for (a, b) in original.data.iter().zip(&image.data) {
+            assert!(a.abs_diff(*b) <= 1);
+        }
+        let alpha_untouched = original.data.iter().skip(3).step_by(4).eq(image.data.iter().skip(3).step_by(4));
+        assert!(alpha_untouched);
+    }
+
+    #[test]
+    fn test_16_bit_and_capacity() {
+        let image = photo(16, 16);
+        let mut deep = Image { bit_depth: 16, data: image.data.iter().flat_map(|&b| [b, b]).collect(), ..image };
+        assert_eq!(capacity(&deep).unwrap(), (16 * 16 * 3 - HEADER_BITS) / 8);
+        embed(&mut deep, &message(50), b"key").unwrap();
+        assert_eq!(extract(&deep, b"key").unwrap(), message(50));
+        assert!(embed(&mut deep, &message(300), b"key").is_err());
+    }
+
+    #[test]
+    fn test_resists_chi_square_attack() {
+        let original = photo(128, 128);
+        let data = message(capacity(&original).unwrap() / 4);
+
+        assert!(chi_square(&original).unwrap() < 0.1);
+
+        // sequential LSB replacement, the naive way
+        let mut naive = original.clone();
+        let indices = cover(&naive).unwrap();
+        let bits = data.iter().flat_map(|&byte| bits_of(byte as u64, 8));
+        for (sample, bit) in indices.into_iter().zip(bits) {
+            naive.data[sample] = naive.data[sample] & !1 | bit;
+        }
+        assert!(chi_square(&naive).unwrap() > 0.9);
+
+        let mut keyed = original.clone();
+        let estimate = embed(&mut keyed, &data, b"key").unwrap();
+        assert!(estimate.chi_square < 0.1);
+        assert_eq!(extract(&keyed, b"key").unwrap(), data);
+    }
+}
@@ -1,34 +1,268 @@
 use clap::Parser;
-use commands::{decode, encode, print, remove};
+use pngme::commands::{self, capacity, Destination, create, decode, encode, history, keygen, optimize, print, remove, rename_type, reorder, view, watermark_detect, watermark_embed};
+use pngme::history::Recorder;
+use pngme::{batch, payload, png, signing, Result};
+
+use config::Strictness;
 
 mod args;
-mod chunk;
-mod chunk_type;
-mod commands;
-mod png;
+mod config;
+
+fn path_string(path: &std::path::Path) -> String {
+    path.to_string_lossy().into_owned()
+}
 
-pub type Error = Box<dyn std::error::Error>;
-pub type Result<T> = std::result::Result<T, Error>;
+/// payload options from the command line, falling back to the config
+fn read_options(
+    config: &config::Config,
+    ecc: Option<u8>,
+    sign: Option<String>,
+    compress: Option<payload::Compression>,
+) -> Result<payload::Options> {
+    let sign = match sign.map(Into::into).or(config.sign_key.clone()) {
+        Some(path) => Some(signing::read_signing_key(&path)?),
+        None => None,
+    };
+    Ok(payload::Options { ecc: ecc.or(config.ecc), sign, compress: compress.or(config.compress) })
+}
+
+/// validates every file before `f` touches it under the strict level
+fn checked<T, F>(strictness: Strictness, f: F) -> impl Fn(&std::path::Path) -> Result<T> + Sync
+where
+    F: Fn(&std::path::Path) -> Result<T> + Sync,
+{
+    move |path| {
+        if strictness == Strictness::Strict {
+            commands::validate(&path_string(path))?;
+        }
+        f(path)
+    }
+}
+
+/// adds a history entry to the file `f` wrote, found with `written`, when a recorder is given
+fn recorded<'a, T, F, W>(recorder: Option<&'a Recorder>, operation: &'a str, written: W, f: F) -> impl Fn(&std::path::Path) -> Result<T> + Sync + 'a
+where
+    F: Fn(&std::path::Path) -> Result<T> + Sync + 'a,
+    W: Fn(&std::path::Path) -> std::path::PathBuf + Sync + 'a,
+{
+    move |path| {
+        let before = match recorder {
+            Some(_) => Some(std::fs::read(path)?),
+            None => None,
+        };
+        let out = f(path)?;
+        if let (Some(recorder), Some(before)) = (recorder, before) {
+            recorder.record(Some(&before), &written(path), operation)?;
+        }
+        Ok(out)
+    }
+}
 
 fn main() -> Result<()> {
     let args = args::Args::parse();
+    let config = config::load(args.profile.as_deref())?;
+    let strictness = args.strictness.or(config.strictness).unwrap_or_default();
+    // the output pattern of the config applies when no --output is given
+    let output_for = |output: &Option<String>, path: &std::path::Path| {
+        output.clone().or_else(|| config.output.as_ref().map(|pattern| config::output_path(pattern, path)))
+    };
+
+    let recorder = match args.history_key.map(Into::into).or(config.history_key.clone()) {
+        Some(path) => Some(Recorder { key: Some(signing::read_signing_key(&path)?) }),
+        None if args.history || config.history == Some(true) => Some(Recorder::default()),
+        None => None,
+    };
+    let recorder = recorder.as_ref();
+    let in_place = |path: &std::path::Path| path.to_path_buf();
+    let written_to = |output: &Option<String>, path: &std::path::Path| output_for(output, path).map_or_else(|| path.to_path_buf(), Into::into);
 
-    match args.command {
-        args::Commands::Encode { file_path, chunk_type, message, output } => {
-            encode(file_path, chunk_type, message, output).expect("cannot encode given message");
-            println!("encoded correctly");
+    let succeeded = match args.command {
+        args::Commands::Encode { targets, message, mode, chunk_type, output, passphrase, dry_run, ecc, sign, compress } => {
+            let files = batch::expand(&targets.paths, targets.recursive)?;
+            if output.is_some() && files.len() > 1 {
+                return Err(Box::new(png::PngError { msg: "--output needs a single input file" }));
+            }
+            let chunk_type = chunk_type.or_else(|| config.chunk_type.clone().filter(|_| mode == commands::Mode::Chunk));
+            let options = read_options(&config, ecc, sign, compress)?;
+            let written = |path: &std::path::Path| written_to(&output, path);
+            let results = batch::run(&files, checked(strictness, recorded(recorder, "encode", written, |path| {
+                let destination = match output_for(&output, path) {
+                    _ if dry_run => Destination::DryRun,
+                    Some(output) => Destination::File(output),
+                    None => Destination::InPlace,
+                };
+                if mode == commands::Mode::Lsb {
+                    let estimate = commands::lsb_estimate(&path_string(path), &message, passphrase.as_deref(), &options)?;
+                    println!("{}: {}", path.display(), estimate);
+                }
+                let location = encode(path_string(path), mode, chunk_type.clone(), message.clone(), passphrase.as_deref(), destination, &options)?;
+                Ok(format!("{} in {}", if dry_run { "would encode" } else { "encoded correctly" }, location))
+            })));
+            batch::report(&results)
         }
-        args::Commands::Decode { file_path, chunk_type } => {
-            let msg = decode(file_path, chunk_type)?;
-            println!("decoded message: {}", msg);
+        args::Commands::Decode { targets, mode, chunk_type, passphrase, verify } => {
+            let files = batch::expand(&targets.paths, targets.recursive)?;
+            let chunk_type = chunk_type.or_else(|| config.chunk_type.clone().filter(|_| mode == commands::Mode::Chunk));
+            let verify = match verify.map(Into::into).or(config.verify_key.clone()) {
+                Some(path) => Some(signing::read_verifying_key(&path)?),
+                None => None,
+            };
+            let results = batch::run(&files, checked(strictness, |path| {
+                let payload = decode(path_string(path), mode, chunk_type.clone(), passphrase.as_deref(), verify.as_ref())?;
+                let mut out = format!("decoded message: {}", payload.message_as_string()?);
+                if let Some(corrected) = payload.corrected {
+                    out.push_str(&format!(" (corrected {} symbols)", corrected));
+                }
+                if verify.is_some() {
+                    out.push_str(" (signature verified)");
+                }
+                Ok(out)
+            }));
+            batch::report(&results)
         }
-        args::Commands::Remove { file_path, chunk_type } => {
-            remove(file_path, chunk_type).expect("cannot remove chunk specified");
-            println!("chunk removed successfully");
+        args::Commands::Remove { targets, chunk_type } => {
+            let files = batch::expand(&targets.paths, targets.recursive)?;
+            let results = batch::run(&files, checked(strictness, recorded(recorder, "remove", in_place, |path| {
+                remove(path_string(path), chunk_type.clone())?;
+                Ok("chunk removed successfully")
+            })));
+            batch::report(&results)
         }
-        args::Commands::Print { file_path } => {
-            print(file_path);
+        args::Commands::Capacity { targets } => {
+            let files = batch::expand(&targets.paths, targets.recursive)?;
+            let results = batch::run(&files, checked(strictness, |path| capacity(path_string(path))));
+            batch::report(&results)
         }
+        args::Commands::Print { targets } => {
+            let files = batch::expand(&targets.paths, targets.recursive)?;
+            let results = batch::run(&files, checked(strictness, |path| print(path_string(path))));
+            batch::report(&results)
+        }
+        args::Commands::Create {
+            output,
+            width,
+            height,
+            color_type,
+            color,
+            noise,
+            seed,
+            raw,
+            message,
+            chunk_type,
+            ecc,
+            sign,
+            compress,
+        } => {
+            let source = match (color, raw) {
+                (Some(color), _) => commands::Source::Solid(commands::parse_color(&color)?),
+                (None, Some(raw)) => commands::Source::Raw(raw),
+                (None, None) if noise => {
+                    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+                    commands::Source::Noise(seed.unwrap_or(now.as_nanos() as u64))
+                }
+                (None, None) => commands::Source::Solid([255; 4]),
+            };
+            let message = match message {
+                Some(msg) => {
+                    let options = read_options(&config, ecc, sign, compress)?;
+                    Some((chunk_type.or(config.chunk_type.clone()), msg, options))
+                }
+                None => None,
+            };
+            let created = create(output.clone(), width, height, color_type, source, message)?;
+            if let Some(recorder) = recorder {
+                recorder.record(None, std::path::Path::new(&output), "create")?;
+            }
+            match created {
+                Some(chunk_type) => println!("png created with message in chunk {}", chunk_type),
+                None => println!("png created"),
+            }
+            true
+        }
+        args::Commands::Reorder { targets, rule, order } => {
+            let files = batch::expand(&targets.paths, targets.recursive)?;
+            let order = match rule {
+                Some(rule) => pngme::reorder::Order::Rule(rule),
+                None => pngme::reorder::Order::Explicit(order),
+            };
+            let results = batch::run(&files, checked(strictness, recorded(recorder, "reorder", in_place, |path| reorder(path_string(path), &order))));
+            batch::report(&results)
+        }
+        args::Commands::RenameType { targets, old, new } => {
+            let files = batch::expand(&targets.paths, targets.recursive)?;
+            let rename = |path: &std::path::Path| rename_type(path_string(path), old.clone(), new.clone());
+            let results = batch::run(&files, checked(strictness, recorded(recorder, "rename-type", in_place, rename)));
+            batch::report(&results)
+        }
+        args::Commands::Optimize { targets, level, idat_size, strip, keep, output } => {
+            let files = batch::expand(&targets.paths, targets.recursive)?;
+            if output.is_some() && files.len() > 1 {
+                return Err(Box::new(png::PngError { msg: "--output needs a single input file" }));
+            }
+            let keep = if keep.is_empty() { config.strip_keep.clone().unwrap_or_default() } else { keep };
+            let options = pngme::optimize::Options { level, idat_size, strip, keep };
+            let written = |path: &std::path::Path| written_to(&output, path);
+            let results = batch::run(&files, checked(strictness, recorded(recorder, "optimize", written, |path| {
+                optimize(path_string(path), output_for(&output, path), &options)
+            })));
+            batch::report(&results)
+        }
+        args::Commands::View { targets, columns, lsb, diff } => {
+            let files = batch::expand(&targets.paths, targets.recursive)?;
+            let columns = columns
+                .or_else(|| std::env::var("COLUMNS").ok()?.parse().ok())
+                .unwrap_or(80);
+            let results = batch::run(&files, checked(strictness, |path| {
+                let preview = view(path_string(path), columns, lsb, diff.clone())?;
+                // keeps the preview of every file starting on its own line
+                Ok(if files.len() > 1 { format!("\n{}", preview) } else { preview })
+            }));
+            batch::report(&results)
+        }
+        args::Commands::Keygen { prefix } => {
+            let (secret_path, public_path) = keygen(prefix)?;
+            println!("wrote secret key to {} and public key to {}", secret_path, public_path);
+            true
+        }
+        args::Commands::History { targets, verify } => {
+            let files = batch::expand(&targets.paths, targets.recursive)?;
+            let verify = match verify {
+                Some(path) => Some(signing::read_verifying_key(std::path::Path::new(&path))?),
+                None => None,
+            };
+            let results = batch::run(&files, checked(strictness, |path| {
+                let listing = history(path_string(path), verify.as_ref())?;
+                Ok(if files.len() > 1 { format!("\n{}", listing) } else { listing })
+            }));
+            batch::report(&results)
+        }
+        args::Commands::Shell { path } => {
+            if strictness == Strictness::Strict {
+                commands::validate(&path)?;
+            }
+            pngme::shell::run(&path, recorder, std::io::stdin().lock(), std::io::stdout())?;
+            true
+        }
+        args::Commands::Watermark { action: args::WatermarkAction::Embed { id, targets, key, strength, output } } => {
+            let files = batch::expand(&targets.paths, targets.recursive)?;
+            if output.is_some() && files.len() > 1 {
+                return Err(Box::new(png::PngError { msg: "--output needs a single input file" }));
+            }
+            let written = |path: &std::path::Path| written_to(&output, path);
+            let results = batch::run(&files, checked(strictness, recorded(recorder, "watermark", written, |path| {
+                watermark_embed(path_string(path), id, &key, strength, output_for(&output, path))
+            })));
+            batch::report(&results)
+        }
+        args::Commands::Watermark { action: args::WatermarkAction::Detect { targets, key } } => {
+            let files = batch::expand(&targets.paths, targets.recursive)?;
+            let results = batch::run(&files, checked(strictness, |path| Ok(watermark_detect(path_string(path), &key)?.to_string())));
+            batch::report(&results)
+        }
+    };
+
+    if !succeeded {
+        std::process::exit(1);
     }
 
     Ok(())
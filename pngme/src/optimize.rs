@@ -0,0 +1,168 @@
+use std::fmt;
+
+use crate::image::{self, FilterStrategy, Ihdr};
+use crate::limits::Limits;
+use crate::png::{Png, PngError};
+use crate::Result;
+
+/// ancillary chunks that are not safe to copy but change how the pixels look;
+/// the pixels are left untouched, so `strip` keeps them
+const DISPLAY_CHUNKS: [&str; 5] = ["tRNS", "gAMA", "cHRM", "sRGB", "iCCP"];
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// zlib level between 0 and 9
+    pub level: u32,
+    /// largest `IDAT` chunk, all image data goes in one chunk when `None`
+    pub idat_size: Option<usize>,
+    /// drops ancillary chunks that are neither safe to copy nor needed to display the image
+    pub strip: bool,
+    /// more ancillary chunk types for `strip` to keep
+    pub keep: Vec<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options { level: 9, idat_size: None, strip: false, keep: Vec::new() }
+    }
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub before: usize,
+    pub after: usize,
+    /// types of the chunks dropped by `strip`
+    pub stripped: Vec<String>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let saved = self.before as i64 - self.after as i64;
+        let percent = saved as f64 * 100.0 / self.before.max(1) as f64;
+        write!(f, "{} -> {} bytes, saved {} ({:.1}%)", self.before, self.after, saved, percent)?;
+        if !self.stripped.is_empty() {
+            write!(f, ", dropped {}", self.stripped.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+/// smallest `IDAT` stream over every filter strategy, `None` if none beats `current`
+fn recompress(ihdr: &Ihdr, current: &[u8], level: u32) -> Result<Option<Vec<u8>>> {
+    let passes = image::decode_passes(ihdr, &Limits::default().inflate(current)?)?;
+    let mut best: Option<Vec<u8>> = None;
+    for strategy in FilterStrategy::ALL {
+        let compressed = image::zlib_compress_level(&image::encode_passes_with(ihdr, &passes, strategy), level)?;
+        if compressed.len() < best.as_ref().map_or(current.len(), |best| best.len()) {
+            best = Some(compressed);
+        }
+    }
+
+    Ok(best)
+}
+
+/// re-deflates and re-splits the image data of `png`; every safe-to-copy
+/// chunk is kept, pngme messages included
+pub fn optimize(png: &mut Png, options: &Options) -> Result<Report> {
+    let before = png.as_bytes().len();
+    let ihdr = Ihdr::try_from(png.chunk_by_type("IHDR").ok_or(PngError { msg: "png has no IHDR chunk" })?.data())?;
+
+    let current = png.image_data();
+    let data = recompress(&ihdr, &current, options.level)?.unwrap_or(current);
+    png.set_image_data_split(data, options.idat_size.unwrap_or(usize::MAX))?;
+
+    let stripped = if options.strip { strip(png, &options.keep) } else { Vec::new() };
+
+    Ok(Report { before, after: png.as_bytes().len(), stripped })
+}
+
+/// drops ancillary chunks that are neither safe to copy, needed to display
+/// the image nor listed in `keep`; returns the types dropped
+pub fn strip(png: &mut Png, keep: &[String]) -> Vec<String> {
+    let mut stripped = Vec::new();
+    png.retain_chunks(|ch| {
+        let chunk_type = ch.chunk_type();
+        let name = chunk_type.to_string();
+        let kept = chunk_type.is_critical()
+            || chunk_type.is_safe_to_copy()
+            || DISPLAY_CHUNKS.contains(&name.as_str())
+            || keep.contains(&name);
+        if !kept {
+            stripped.push(name);
+        }
+        kept
+    });
+
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use crate::image::{ColorType, Image};
+    use std::str::FromStr;
+
+    fn bloated_png() -> Png {
+        let image = Image::solid(64, 64, [10, 200, 30, 255], ColorType::Rgb).unwrap();
+        let mut png = Png::from_image(&image).unwrap();
+        let filtered = Limits::default().inflate(&png.image_data()).unwrap();
+        png.set_image_data_split(image::zlib_compress_level(&filtered, 0).unwrap(), 100).unwrap();
+        png
+    }
+
+    fn pixels(png: &Png) -> Vec<Vec<u8>> {
+        let ihdr = Ihdr::try_from(png.chunk_by_type("IHDR").unwrap().data()).unwrap();
+        image::decode_passes(&ihdr, &Limits::default().inflate(&png.image_data()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_optimize_shrinks_and_keeps_pixels() {
+        let mut png = bloated_png();
+        let original = pixels(&png);
+
+        let report = optimize(&mut png, &Options::default()).unwrap();
+        assert!(report.after < report.before);
+        assert_eq!(pixels(&png), original);
+        assert_eq!(png.chunks().iter().filter(|ch| ch.chunk_type().bytes() == *b"IDAT").count(), 1);
+    }
+
+    #[test]
+    fn test_optimize_splits_idat() {
+        let mut png = bloated_png();
+        let options = Options { level: 0, idat_size: Some(1000), ..Options::default() };
+        optimize(&mut png, &options).unwrap();
+
+        let idats: Vec<_> = png.chunks().iter().filter(|ch| ch.chunk_type().bytes() == *b"IDAT").collect();
+        assert!(idats.len() > 1);
+        assert!(idats.iter().all(|ch| ch.length() <= 1000));
+    }
+
+    #[test]
+    fn test_strip_keeps_safe_to_copy_chunks() {
+        let mut png = bloated_png();
+        let end = png.remove_chunk("IEND").unwrap();
+        for (chunk_type, data) in [("ruSt", "message"), ("ruST", "stale"), ("tIME", "xxxxxxx"), ("gAMA", "gama")] {
+            png.append_chunk(Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.as_bytes().to_vec()));
+        }
+        png.append_chunk(end);
+
+        let report = optimize(&mut png, &Options { strip: true, ..Options::default() }).unwrap();
+        assert_eq!(report.stripped, vec!["ruST", "tIME"]);
+        assert!(png.chunk_by_type("ruSt").is_some());
+        assert!(png.chunk_by_type("gAMA").is_some());
+    }
+
+    #[test]
+    fn test_strip_keeps_listed_chunks() {
+        let mut png = bloated_png();
+        let end = png.remove_chunk("IEND").unwrap();
+        png.append_chunk(Chunk::new(ChunkType::from_str("tIME").unwrap(), b"xxxxxxx".to_vec()));
+        png.append_chunk(end);
+
+        let options = Options { strip: true, keep: vec![String::from("tIME")], ..Options::default() };
+        assert!(optimize(&mut png, &options).unwrap().stripped.is_empty());
+        assert!(png.chunk_by_type("tIME").is_some());
+    }
+}
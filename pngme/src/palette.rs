@@ -0,0 +1,301 @@
+use std::str::FromStr;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::image::{self, ColorType, Ihdr};
+use crate::limits::Limits;
+use crate::png::{Png, PngError, PngRef};
+use crate::{Error, Result};
+
+// Messages are hidden in the order of the `PLTE` entries. Sorting the entries
+// gives a canonical order, the message is turned into a number below n! and
+// that number picks one of the n! permutations of the sorted palette. The
+// indices in `IDAT` are rewritten so every pixel keeps its color.
+
+/// first byte of the hidden number, keeps leading zero bytes of the length
+const MARKER: u8 = 1;
+
+fn palette_error(msg: &'static str) -> Error {
+    Box::new(PngError { msg })
+}
+
+/// rgba palette entries, alpha comes from `tRNS` when present
+fn entries(plte: &[u8], trns: Option<&[u8]>) -> Result<Vec<[u8; 4]>> {
+    if plte.is_empty() || !plte.len().is_multiple_of(3) || plte.len() > 256 * 3 {
+        return Err(palette_error("invalid PLTE chunk"));
+    }
+    let trns = trns.unwrap_or(&[]);
+    let entries: Vec<[u8; 4]> = plte
+        .chunks_exact(3)
+        .enumerate()
+        .map(|(i, rgb)| [rgb[0], rgb[1], rgb[2], trns.get(i).copied().unwrap_or(255)])
+        .collect();
+
+    let mut sorted = entries.clone();
+    sorted.sort();
+    sorted.dedup();
+    if sorted.len() != entries.len() {
+        return Err(palette_error("palette has duplicate entries"));
+    }
+
+    Ok(entries)
+}
+
+/// bits that can be stored in the order of `n` entries, floor(log2(n!))
+fn capacity_bits(n: usize) -> usize {
+    (2..=n).map(|k| (k as f64).log2()).sum::<f64>().floor() as usize
+}
+
+/// bytes of message that fit in a palette of `n` distinct entries
+pub fn capacity(n: usize) -> usize {
+    // the marker bit and the two length bytes are not available for the message
+    (capacity_bits(n).saturating_sub(1) / 8).saturating_sub(2)
+}
+
+/// divides a big endian number in place, returning the remainder
+fn div_rem(number: &mut Vec<u8>, divisor: u32) -> u32 {
+    let mut rem = 0;
+    for byte in number.iter_mut() {
+        let cur = rem << 8 | *byte as u32;
+        *byte = (cur / divisor) as u8;
+        rem = cur % divisor;
+    }
+    let leading = number.iter().take_while(|&&b| b == 0).count();
+    number.drain(..leading);
+    rem
+}
+
+/// computes `number * factor + term` in place on a big endian number
+fn mul_add(number: &mut Vec<u8>, factor: u32, term: u32) {
+    let mut carry = term;
+    for byte in number.iter_mut().rev() {
+        let cur = *byte as u32 * factor + carry;
+        *byte = cur as u8;
+        carry = cur >> 8;
+    }
+    while carry > 0 {
+        number.insert(0, carry as u8);
+        carry >>= 8;
+    }
+}
+
+/// new order of the palette as indices into `entries`
+fn permutation_for(entries: &[[u8; 4]], data: &[u8]) -> Result<Vec<usize>> {
+    let n = entries.len();
+    if data.len() > capacity(n) || data.len() > u16::MAX as usize {
+        return Err(palette_error("message does not fit in the palette"));
+    }
+
+    let mut number: Vec<u8> = [&[MARKER][..], &(data.len() as u16).to_be_bytes(), data].concat();
+    // lehmer code of the permutation, the digit at position i is below n - i
+    let mut digits = vec![0; n];
+    for i in (0..n).rev() {
+        digits[i] = div_rem(&mut number, (n - i) as u32) as usize;
+    }
+
+    let mut available: Vec<usize> = (0..n).collect();
+    available.sort_by_key(|&i| entries[i]);
+    Ok(digits.into_iter().map(|digit| available.remove(digit)).collect())
+}
+
+/// reverses [`permutation_for`] given the palette as stored in the file
+fn data_from_order(entries: &[[u8; 4]]) -> Result<Vec<u8>> {
+    let mut available = entries.to_vec();
+    available.sort();
+
+    let mut number = Vec::new();
+    for (i, entry) in entries.iter().enumerate() {
+        let digit = available.iter().position(|e| e == entry).unwrap();
+        available.remove(digit);
+        mul_add(&mut number, (entries.len() - i) as u32, digit as u32);
+    }
+
+    match number.split_first() {
+        Some((&MARKER, rest)) if rest.len() >= 2 => {
+            let len = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+            if rest.len() - 2 != len {
+                return Err(palette_error("no message hidden in the palette"));
+            }
+            Ok(rest[2..].to_vec())
+        }
+        _ => Err(palette_error("no message hidden in the palette")),
+    }
+}
+
+fn indexed_header(ihdr: &[u8]) -> Result<Ihdr> {
+    let ihdr = Ihdr::try_from(ihdr)?;
+    if ihdr.color_type != ColorType::Indexed {
+        return Err(palette_error("palette mode needs an indexed image"));
+    }
+    Ok(ihdr)
+}
+
+/// message capacity in bytes of the palette of an indexed png
+pub fn capacity_of(png: &PngRef) -> Result<usize> {
+    let ihdr = png.chunk_by_type("IHDR").ok_or(palette_error("png has no IHDR chunk"))?;
+    indexed_header(ihdr.data())?;
+    let plte = png.chunk_by_type("PLTE").ok_or(palette_error("png has no PLTE chunk"))?;
+    let trns = png.chunk_by_type("tRNS").map(|ch| ch.data());
+
+    Ok(capacity(entries(plte.data(), trns)?.len()))
+}
+
+pub fn extract(png: &PngRef) -> Result<Vec<u8>> {
+    let ihdr = png.chunk_by_type("IHDR").ok_or(palette_error("png has no IHDR chunk"))?;
+    indexed_header(ihdr.data())?;
+    let plte = png.chunk_by_type("PLTE").ok_or(palette_error("png has no PLTE chunk"))?;
+    let trns = png.chunk_by_type("tRNS").map(|ch| ch.data());
+
+    data_from_order(&entries(plte.data(), trns)?)
+}
+
+/// reorders the palette of an indexed png to hide `data`, pixels keep their colors
+pub fn embed(png: &mut Png, data: &[u8]) -> Result<()> {
+    let ihdr = png.chunk_by_type("IHDR").ok_or(palette_error("png has no IHDR chunk"))?;
+    let ihdr = indexed_header(ihdr.data())?;
+    let plte = png.chunk_by_type("PLTE").ok_or(palette_error("png has no PLTE chunk"))?;
+    let trns = png.chunk_by_type("tRNS").map(|ch| ch.data().to_vec());
+    let entries = entries(plte.data(), trns.as_deref())?;
+
+    let order = permutation_for(&entries, data)?;
+    let mut new_index = vec![0u8; entries.len()];
+    for (new, &old) in order.iter().enumerate() {
+        new_index[old] = new as u8;
+    }
+
+    let inflated = Limits::default().inflate(&png.image_data())?;
+    let mut passes = image::decode_passes(&ihdr, &inflated)?;
+    for (pass, (width, _)) in passes.iter_mut().zip(ihdr.passes()) {
+        let stride = ihdr.stride(width);
+        for row in pass.chunks_exact_mut(stride) {
+            let samples = image::unpack_samples(row, ihdr.bit_depth, width as usize);
+            let remapped = samples
+                .iter()
+                .map(|&idx| new_index.get(idx as usize).copied())
+                .collect::<Option<Vec<u8>>>()
+                .ok_or(palette_error("pixel index outside of the palette"))?;
+            row.copy_from_slice(&image::pack_samples(&remapped, ihdr.bit_depth));
+        }
+    }
+    png.set_image_data(image::zlib_compress(&image::encode_passes(&ihdr, &passes))?)?;
+
+    let plte: Vec<u8> = order.iter().flat_map(|&old| entries[old][..3].to_vec()).collect();
+    png.replace_chunk(Chunk::new(ChunkType::from_str("PLTE")?, plte))?;
+    if trns.is_some() {
+        let mut alpha: Vec<u8> = order.iter().map(|&old| entries[old][3]).collect();
+        while alpha.last() == Some(&255) {
+            alpha.pop();
+        }
+        png.replace_chunk(Chunk::new(ChunkType::from_str("tRNS")?, alpha))?;
+    }
+    if let Some(hist) = png.chunk_by_type("hIST").map(|ch| ch.data().to_vec()) {
+        if hist.len() == entries.len() * 2 {
+            let hist: Vec<u8> = order.iter().flat_map(|&old| hist[old * 2..old * 2 + 2].to_vec()).collect();
+            png.replace_chunk(Chunk::new(ChunkType::from_str("hIST")?, hist))?;
+        }
+    }
+    if let Some(&[idx]) = png.chunk_by_type("bKGD").map(|ch| ch.data()) {
+        if let Some(&new) = new_index.get(idx as usize) {
+            png.replace_chunk(Chunk::new(ChunkType::from_str("bKGD")?, vec![new]))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::Image;
+
+    fn indexed_png(colors: u8, width: u32, height: u32) -> Png {
+        let rgba: Vec<u8> = (0..width * height)
+            .flat_map(|i| {
+                let c = (i % colors as u32) as u8;
+                [c.wrapping_mul(37), c.wrapping_mul(11), 255 - c, 255]
+            })
+            .collect();
+        Png::from_image(&Image::from_rgba(width, height, &rgba, ColorType::Indexed).unwrap()).unwrap()
+    }
+
+    fn rgba_pixels(png: &Png) -> Vec<u8> {
+        let bytes = png.as_bytes();
+        let mut decoder = ::png::Decoder::new(&bytes[..]);
+        decoder.set_transformations(::png::Transformations::EXPAND);
+        let mut reader = decoder.read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_capacity() {
+        assert_eq!(capacity(1), 0);
+        // log2(256!) is about 1684 bits
+        assert_eq!(capacity(256), 208);
+    }
+
+    #[test]
+    fn test_big_number_helpers() {
+        let mut number = vec![1, 0, 1];
+        assert_eq!(div_rem(&mut number, 7), 65537 % 7);
+        mul_add(&mut number, 7, 65537 % 7);
+        assert_eq!(number, vec![1, 0, 1]);
+    }
+
+    #[test]
+    fn test_permutation_round_trip() {
+        let entries: Vec<[u8; 4]> = (0..40).map(|i| [i * 3, 255 - i, i, 255]).collect();
+        let order = permutation_for(&entries, b"hidden!").unwrap();
+        let permuted: Vec<[u8; 4]> = order.iter().map(|&i| entries[i]).collect();
+        assert_eq!(data_from_order(&permuted).unwrap(), b"hidden!");
+    }
+
+    #[test]
+    fn test_embed_keeps_pixels() {
+        let mut png = indexed_png(64, 20, 10);
+        let before = rgba_pixels(&png);
+
+        embed(&mut png, b"palette secret").unwrap();
+        assert_eq!(rgba_pixels(&png), before);
+
+        let bytes = png.as_bytes();
+        let png_ref = PngRef::try_from(&bytes[..]).unwrap();
+        assert_eq!(extract(&png_ref).unwrap(), b"palette secret");
+        assert_eq!(capacity_of(&png_ref).unwrap(), capacity(64));
+    }
+
+    #[test]
+    fn test_embed_packed_indices() {
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = ::png::Encoder::new(&mut bytes, 7, 3);
+            encoder.set_color(::png::ColorType::Indexed);
+            encoder.set_depth(::png::BitDepth::Four);
+            encoder.set_palette((0..16u8).flat_map(|i| [i * 16, 0, 255 - i]).collect::<Vec<u8>>());
+            let mut writer = encoder.write_header().unwrap();
+            let rows: Vec<u8> = (0..3u8).flat_map(|y| [y << 4 | 1, 0x23, 0x45, (y + 9) << 4]).collect();
+            writer.write_image_data(&rows).unwrap();
+        }
+        let mut png = Png::try_from(&bytes[..]).unwrap();
+        let before = rgba_pixels(&png);
+
+        embed(&mut png, b"4b").unwrap();
+        assert_eq!(rgba_pixels(&png), before);
+        let bytes = png.as_bytes();
+        assert_eq!(extract(&PngRef::try_from(&bytes[..]).unwrap()).unwrap(), b"4b");
+    }
+
+    #[test]
+    fn test_embed_too_long() {
+        let mut png = indexed_png(8, 4, 4);
+        assert!(embed(&mut png, b"far too long for eight colors").is_err());
+    }
+
+    #[test]
+    fn test_untouched_palette_has_no_message() {
+        let png = indexed_png(16, 4, 4);
+        let bytes = png.as_bytes();
+        assert!(extract(&PngRef::try_from(&bytes[..]).unwrap()).is_err());
+    }
+}
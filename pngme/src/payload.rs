@@ -0,0 +1,519 @@
+use crate::limits::Limits;
+use crate::{image, Error, Result};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey, SIGNATURE_LENGTH};
+use reed_solomon::{Decoder, Encoder};
+use std::fmt;
+use std::io::Read;
+
+const MAGIC: [u8; 4] = *b"pnMe";
+const VERSION: u8 = 1;
+const FLAG_ECC: u8 = 1;
+const FLAG_SIGNED: u8 = 2;
+const FLAG_ZLIB: u8 = 4;
+const FLAG_ZSTD: u8 = 8;
+const KNOWN_FLAGS: u8 = FLAG_ECC | FLAG_SIGNED | FLAG_ZLIB | FLAG_ZSTD;
+
+/// magic, version, flags, parity length, message length and encoded length
+const ECC_HEADER_LEN: usize = 15;
+const ECC_HEADER_COPIES: usize = 3;
+const RS_BLOCK_LEN: usize = 255;
+
+#[derive(Debug)]
+pub struct PayloadError {
+    pub msg: &'static str,
+}
+
+impl fmt::Display for PayloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "error while processing payload: {}", self.msg)
+    }
+}
+
+impl std::error::Error for PayloadError {}
+
+fn payload_error(msg: &'static str) -> Error {
+    Box::new(PayloadError { msg })
+}
+
+/// algorithm a message is compressed with before it is stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Zlib,
+    Zstd,
+}
+
+impl Compression {
+    fn flag(self) -> u8 {
+        match self {
+            Compression::Zlib => FLAG_ZLIB,
+            Compression::Zstd => FLAG_ZSTD,
+        }
+    }
+
+    fn compress(self, message: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Compression::Zlib => image::zlib_compress_level(message, 9),
+            Compression::Zstd => Ok(zstd::encode_all(message, 19)?),
+        }
+    }
+}
+
+/// reverses the compression named by `flags`, within the decompressed size
+/// of the default `Limits`
+fn decompress(flags: u8, data: &[u8]) -> Result<Vec<u8>> {
+    let limits = Limits::default();
+    if flags & FLAG_ZLIB != 0 {
+        return limits.inflate(data);
+    }
+    if flags & FLAG_ZSTD != 0 {
+        let mut message = Vec::new();
+        zstd::Decoder::new(data)?
+            .take(limits.max_decompressed_size as u64 + 1)
+            .read_to_end(&mut message)?;
+        if message.len() > limits.max_decompressed_size {
+            return Err(payload_error("compressed message exceeds the maximum decompressed size"));
+        }
+        return Ok(message);
+    }
+
+    Ok(data.to_vec())
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+    /// parity bytes added to every 255 byte reed-solomon block
+    pub ecc: Option<u8>,
+    /// signs the message together with where it is stored
+    pub sign: Option<SigningKey>,
+    /// compresses the message, unless that does not make it smaller
+    pub compress: Option<Compression>,
+}
+
+impl Options {
+    fn flags(&self) -> u8 {
+        let mut flags = 0;
+        if self.ecc.is_some() {
+            flags |= FLAG_ECC;
+        }
+        if self.sign.is_some() {
+            flags |= FLAG_SIGNED;
+        }
+        flags
+    }
+}
+
+#[derive(Debug)]
+pub struct Payload {
+    pub message: Vec<u8>,
+    /// symbols fixed by error correction, `None` when the payload carries no parity
+    pub corrected: Option<usize>,
+    pub signature: Option<Signature>,
+}
+
+/// bytes covered by a signature: the location of the message, its chunk type
+/// or `PLTE` in palette mode, followed by the message itself
+fn signed_bytes(location: &[u8], message: &[u8]) -> Vec<u8> {
+    [location, message].concat()
+}
+
+impl Payload {
+    pub fn message_as_string(&self) -> Result<String> {
+        Ok(String::from_utf8(self.message.clone())?)
+    }
+
+    pub fn verify(&self, key: &VerifyingKey, location: &[u8]) -> Result<()> {
+        let signature = self.signature.ok_or_else(|| payload_error("message is not signed"))?;
+        key.verify(&signed_bytes(location, &self.message), &signature)
+            .map_err(|_| payload_error("signature does not match the message"))
+    }
+
+    fn from_body(flags: u8, body: &[u8], corrected: Option<usize>) -> Result<Payload> {
+        if flags & !KNOWN_FLAGS != 0 {
+            return Err(payload_error("payload uses features this version does not know"));
+        }
+        if flags & FLAG_SIGNED == 0 {
+            return Ok(Payload { message: decompress(flags, body)?, corrected, signature: None });
+        }
+        if body.len() < SIGNATURE_LENGTH {
+            return Err(payload_error("signed payload is too short"));
+        }
+
+        let (signature, message) = body.split_at(SIGNATURE_LENGTH);
+        Ok(Payload {
+            message: decompress(flags, message)?,
+            corrected,
+            signature: Some(Signature::from_slice(signature)?),
+        })
+    }
+}
+
+/// wraps a message so it can be stored at `location`; without any option set
+/// the message is stored verbatim, like older versions of pngme did. The
+/// signature covers the message before compression
+pub fn wrap(message: &[u8], location: &[u8], options: &Options) -> Result<Vec<u8>> {
+    let mut flags = options.flags();
+    let mut stored = message.to_vec();
+    if let Some(compression) = options.compress {
+        let compressed = compression.compress(message)?;
+        // with the header short messages would only grow
+        if compressed.len() + MAGIC.len() + 2 < message.len() {
+            flags |= compression.flag();
+            stored = compressed;
+        }
+    }
+    if flags == 0 {
+        return Ok(stored);
+    }
+
+    let body = match &options.sign {
+        Some(key) => {
+            let signature = key.sign(&signed_bytes(location, message));
+            [&signature.to_bytes()[..], &stored].concat()
+        }
+        None => stored,
+    };
+
+    match options.ecc {
+        None => Ok([&MAGIC[..], &[VERSION, flags], &body].concat()),
+        Some(parity) => {
+            let parity = parity as usize;
+            if parity == 0 || parity >= RS_BLOCK_LEN {
+                return Err(payload_error("parity length must be between 1 and 254"));
+            }
+            let encoded = ecc_encode(&body, parity);
+            let mut header = Vec::with_capacity(ECC_HEADER_LEN);
+            header.extend_from_slice(&MAGIC);
+            header.extend_from_slice(&[VERSION, flags, parity as u8]);
+            header.extend_from_slice(&(body.len() as u32).to_be_bytes());
+            header.extend_from_slice(&(encoded.len() as u32).to_be_bytes());
+
+            Ok(header.repeat(ECC_HEADER_COPIES).into_iter().chain(encoded).collect())
+        }
+    }
+}
+
+/// reverses [`wrap`], data without a pngme header is returned as is
+pub fn unwrap(data: &[u8]) -> Result<Payload> {
+    if let Some(header) = voted_ecc_header(data)? {
+        let parity = header[6] as usize;
+        let body_len = u32::from_be_bytes(header[7..11].try_into()?) as usize;
+        let encoded_len = u32::from_be_bytes(header[11..15].try_into()?) as usize;
+        let encoded = data.get(ECC_HEADER_LEN * ECC_HEADER_COPIES..).unwrap_or_default();
+        let (body, corrected) = ecc_decode(encoded, parity, body_len, encoded_len)?;
+
+        return Payload::from_body(header[5], &body, Some(corrected));
+    }
+
+    if data.len() >= MAGIC.len() + 2 && data[..MAGIC.len()] == MAGIC {
+        if data[MAGIC.len()] != VERSION {
+            return Err(payload_error("unsupported payload version"));
+        }
+        return Payload::from_body(data[MAGIC.len() + 1], &data[MAGIC.len() + 2..], None);
+    }
+
+    Ok(Payload { message: data.to_vec(), corrected: None, signature: None })
+}
+
+fn is_ecc_header(header: &[u8]) -> bool {
+    header.len() > 5 && header[..MAGIC.len()] == MAGIC && header[4] == VERSION && header[5] & FLAG_ECC != 0
+}
+
+/// the ecc header is stored three times, each byte is decided by majority;
+/// data cut inside the third copy still has two copies, which must agree
+fn voted_ecc_header(data: &[u8]) -> Result<Option<[u8; ECC_HEADER_LEN]>> {
+    let copies: Vec<&[u8]> = data.chunks_exact(ECC_HEADER_LEN).take(ECC_HEADER_COPIES).collect();
+    let mut header = [0; ECC_HEADER_LEN];
+    let voted = match copies[..] {
+        [first, second, third] => {
+            for (i, byte) in header.iter_mut().enumerate() {
+                *byte = if second[i] == third[i] { second[i] } else { first[i] };
+            }
+            true
+        }
+        [first, second] if first == second => {
+            header.copy_from_slice(first);
+            true
+        }
+        _ => false,
+    };
+
+    if voted {
+        Ok(is_ecc_header(&header).then_some(header))
+    } else if is_ecc_header(data) {
+        Err(payload_error("error correction header is truncated or damaged"))
+    } else {
+        Ok(None)
+    }
+}
+
+/// data length of each reed-solomon block the message is split into
+fn block_data_lens(message_len: usize, parity: usize) -> Vec<usize> {
+    let block_data_len = RS_BLOCK_LEN - parity;
+    let blocks = message_len.div_ceil(block_data_len).max(1);
+    (0..blocks)
+        .map(|i| block_data_len.min(message_len - i * block_data_len))
+        .collect()
+}
+
+/// position in the encoded body of every byte of every block; blocks are
+/// interleaved so a truncated or damaged run spreads over all of them
+fn interleaving(message_len: usize, parity: usize) -> Vec<Vec<usize>> {
+    let block_lens: Vec<usize> = block_data_lens(message_len, parity)
+        .into_iter()
+        .map(|len| len + parity)
+        .collect();
+    let mut positions: Vec<Vec<usize>> = block_lens.iter().map(|&len| Vec::with_capacity(len)).collect();
+    let longest = block_lens.iter().copied().max().unwrap_or(0);
+
+    let mut next = 0;
+    for offset in 0..longest {
+        for (block, &len) in block_lens.iter().enumerate() {
+            if offset < len {
+                positions[block].push(next);
+                next += 1;
+            }
+        }
+    }
+
+    positions
+}
+
+fn ecc_encode(message: &[u8], parity: usize) -> Vec<u8> {
+    let encoder = Encoder::new(parity);
+    let positions = interleaving(message.len(), parity);
+    let mut body = vec![0; positions.iter().map(Vec::len).sum()];
+
+    let mut start = 0;
+    for (len, block_positions) in block_data_lens(message.len(), parity).into_iter().zip(&positions) {
+        let block = encoder.encode(&message[start..start + len]);
+        for (&byte, &pos) in block.iter().zip(block_positions) {
+            body[pos] = byte;
+        }
+        start += len;
+    }
+
+    body
+}
+
+/// checks the header lengths against each other and against the data that is
+/// actually there, before anything is allocated for them
+fn check_ecc_header(available: usize, parity: usize, message_len: usize, encoded_len: usize) -> Result<()> {
+    if parity == 0 || parity >= RS_BLOCK_LEN {
+        return Err(payload_error("parity length must be between 1 and 254"));
+    }
+    if message_len > Limits::default().max_decompressed_size {
+        return Err(payload_error("message exceeds the maximum decompressed size"));
+    }
+    let blocks = message_len.div_ceil(RS_BLOCK_LEN - parity).max(1);
+    if message_len + blocks * parity != encoded_len {
+        return Err(payload_error("inconsistent error correction header"));
+    }
+    // every block can only recover as many missing bytes as it has parity
+    if encoded_len.saturating_sub(available) > blocks * parity {
+        return Err(payload_error("too many errors to correct"));
+    }
+
+    Ok(())
+}
+
+fn ecc_decode(body: &[u8], parity: usize, message_len: usize, encoded_len: usize) -> Result<(Vec<u8>, usize)> {
+    check_ecc_header(body.len(), parity, message_len, encoded_len)?;
+    let positions = interleaving(message_len, parity);
+
+    let decoder = Decoder::new(parity);
+    let mut message = Vec::with_capacity(message_len);
+    let mut corrected = 0;
+    for block_positions in positions {
+        let block: Vec<u8> = block_positions.iter().map(|&pos| body.get(pos).copied().unwrap_or(0)).collect();
+        let erasures: Vec<u8> = block_positions
+            .iter()
+            .enumerate()
+            .filter(|(_, &pos)| pos >= body.len())
+            .map(|(i, _)| i as u8)
+            .collect();
+
+        let (fixed, count) = decoder
+            .correct_err_count(&block, Some(&erasures))
+            .map_err(|_| payload_error("too many errors to correct"))?;
+        message.extend_from_slice(fixed.data());
+        corrected += count;
+    }
+
+    Ok((message, corrected))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn long_message() -> Vec<u8> {
+        (0..1000u32).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_plain_message_is_stored_verbatim() {
+        let wrapped = wrap(b"hello", b"ruSt", &Options::default()).unwrap();
+        assert_eq!(wrapped, b"hello");
+        assert_eq!(unwrap(&wrapped).unwrap().message, b"hello");
+    }
+
+    #[test]
+    fn test_ecc_round_trip() {
+        let options = Options { ecc: Some(16), ..Options::default() };
+        let payload = unwrap(&wrap(&long_message(), b"ruSt", &options).unwrap()).unwrap();
+        assert_eq!(payload.message, long_message());
+        assert_eq!(payload.corrected, Some(0));
+    }
+
+    #[test]
+    fn test_ecc_corrects_flipped_bytes() {
+        let options = Options { ecc: Some(16), ..Options::default() };
+        let mut wrapped = wrap(&long_message(), b"ruSt", &options).unwrap();
+        for i in [3, 20, 100, 101, 102, 500, 900] {
+            wrapped[i] ^= 0xff;
+        }
+
+        let payload = unwrap(&wrapped).unwrap();
+        assert_eq!(payload.message, long_message());
+        assert_eq!(payload.corrected, Some(5));
+    }
+
+    #[test]
+    fn test_ecc_survives_truncation() {
+        let options = Options { ecc: Some(32), ..Options::default() };
+        let wrapped = wrap(&long_message(), b"ruSt", &options).unwrap();
+
+        let payload = unwrap(&wrapped[..wrapped.len() - 40]).unwrap();
+        assert_eq!(payload.message, long_message());
+        assert_eq!(payload.corrected, Some(40));
+    }
+
+    #[test]
+    fn test_ecc_truncated_in_header_copies() {
+        let options = Options { ecc: Some(32), ..Options::default() };
+        let wrapped = wrap(b"", b"ruSt", &options).unwrap();
+        // inside the third copy: the two first copies agree and the whole body is missing
+        let payload = unwrap(&wrapped[..ECC_HEADER_LEN * 2 + 7]).unwrap();
+        assert_eq!(payload.message, b"");
+
+        let wrapped = wrap(&long_message(), b"ruSt", &options).unwrap();
+        assert!(unwrap(&wrapped[..ECC_HEADER_LEN * 2 + 7]).is_err());
+        assert!(unwrap(&wrapped[..ECC_HEADER_LEN + 7]).is_err());
+    }
+
+    #[test]
+    fn test_ecc_too_many_errors() {
+        let options = Options { ecc: Some(2), ..Options::default() };
+        let mut wrapped = wrap(b"short message", b"ruSt", &options).unwrap();
+        let len = wrapped.len();
+        for byte in &mut wrapped[len - 5..] {
+            *byte ^= 0x55;
+        }
+
+        assert!(unwrap(&wrapped).is_err());
+    }
+
+    /// three copies of an ecc header claiming the given lengths, then `body`
+    fn crafted(parity: u8, message_len: u32, encoded_len: u32, body: &[u8]) -> Vec<u8> {
+        let mut header = [&MAGIC[..], &[VERSION, FLAG_ECC, parity]].concat();
+        header.extend_from_slice(&message_len.to_be_bytes());
+        header.extend_from_slice(&encoded_len.to_be_bytes());
+        [header.repeat(ECC_HEADER_COPIES), body.to_vec()].concat()
+    }
+
+    #[test]
+    fn test_crafted_ecc_header_parity() {
+        assert!(unwrap(&crafted(255, 10, 10, &[0; 10])).is_err());
+        assert!(unwrap(&crafted(0, 10, 10, &[0; 10])).is_err());
+    }
+
+    #[test]
+    fn test_crafted_ecc_header_lengths() {
+        assert!(unwrap(&crafted(0, u32::MAX, u32::MAX, &[0; 15])).is_err());
+        // consistent lengths, but almost none of the data is there
+        let message_len: u32 = 1 << 29;
+        let encoded_len = message_len + message_len.div_ceil(254);
+        assert!(unwrap(&crafted(1, message_len, encoded_len, &[0; 15])).is_err());
+        assert!(unwrap(&crafted(1, 1000, 1000, &[0; 15])).is_err());
+    }
+
+    #[test]
+    fn test_signed_payload() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let options = Options { sign: Some(key.clone()), ..Options::default() };
+        let payload = unwrap(&wrap(b"from the team", b"ruSt", &options).unwrap()).unwrap();
+
+        assert_eq!(payload.message, b"from the team");
+        assert!(payload.verify(&key.verifying_key(), b"ruSt").is_ok());
+        assert!(payload.verify(&key.verifying_key(), b"plTe").is_err());
+        assert!(payload.verify(&SigningKey::from_bytes(&[8; 32]).verifying_key(), b"ruSt").is_err());
+    }
+
+    #[test]
+    fn test_tampered_signed_payload() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let options = Options { sign: Some(key.clone()), ..Options::default() };
+        let mut wrapped = wrap(b"from the team", b"ruSt", &options).unwrap();
+        *wrapped.last_mut().unwrap() ^= 1;
+
+        let payload = unwrap(&wrapped).unwrap();
+        assert!(payload.verify(&key.verifying_key(), b"ruSt").is_err());
+    }
+
+    #[test]
+    fn test_signed_ecc_payload() {
+        let key = SigningKey::from_bytes(&[9; 32]);
+        let options = Options { ecc: Some(8), sign: Some(key.clone()), ..Options::default() };
+        let mut wrapped = wrap(&long_message(), b"ruSt", &options).unwrap();
+        wrapped[300] ^= 0x0f;
+
+        let payload = unwrap(&wrapped).unwrap();
+        assert_eq!(payload.corrected, Some(1));
+        assert!(payload.verify(&key.verifying_key(), b"ruSt").is_ok());
+    }
+
+    #[test]
+    fn test_unsigned_payload_does_not_verify() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let payload = unwrap(b"plain").unwrap();
+        assert!(payload.verify(&key.verifying_key(), b"ruSt").is_err());
+    }
+
+    fn log_lines() -> Vec<u8> {
+        (0..200).flat_map(|i| format!("2024-01-01 12:00:{:02} INFO request handled\n", i % 60).into_bytes()).collect()
+    }
+
+    #[test]
+    fn test_compressed_round_trip() {
+        for compress in [Compression::Zlib, Compression::Zstd] {
+            let options = Options { compress: Some(compress), ..Options::default() };
+            let wrapped = wrap(&log_lines(), b"ruSt", &options).unwrap();
+            assert!(wrapped.len() < log_lines().len() / 4);
+            assert_eq!(unwrap(&wrapped).unwrap().message, log_lines());
+        }
+    }
+
+    #[test]
+    fn test_compression_skipped_when_it_does_not_help() {
+        let options = Options { compress: Some(Compression::Zstd), ..Options::default() };
+        assert_eq!(wrap(b"hi", b"ruSt", &options).unwrap(), b"hi");
+    }
+
+    #[test]
+    fn test_signed_ecc_compressed_payload() {
+        let key = SigningKey::from_bytes(&[5; 32]);
+        let options = Options { ecc: Some(8), sign: Some(key.clone()), compress: Some(Compression::Zlib) };
+        let mut wrapped = wrap(&log_lines(), b"ruSt", &options).unwrap();
+        wrapped[100] ^= 0x40;
+
+        let payload = unwrap(&wrapped).unwrap();
+        assert_eq!(payload.message, log_lines());
+        assert!(payload.verify(&key.verifying_key(), b"ruSt").is_ok());
+    }
+
+    #[test]
+    fn test_unknown_flags_are_refused() {
+        assert!(unwrap(&[&MAGIC[..], &[VERSION, 0x80], b"data"].concat()).is_err());
+    }
+}
@@ -1,7 +1,9 @@
-use crate::chunk::Chunk;
+use crate::chunk::{Chunk, ChunkRef};
 use crate::chunk_type::ChunkType;
+use crate::limits::{Limits, MAX_CHUNK_SIZE};
 use crate::{Error, Result};
-use std::io::{BufReader, Read};
+use std::io::Read;
+#[derive(Debug, PartialEq, Eq)]
 pub struct Png {
     chunks: Vec<Chunk>,
     signature: [u8; 8],
@@ -41,12 +43,26 @@ impl Png {
         self.chunks.push(chunk);
     }
 
+    /// inserts `chunk` at `index`, or last when `index` is past the end
+    pub fn insert_chunk(&mut self, index: usize, chunk: Chunk) {
+        self.chunks.insert(index.min(self.chunks.len()), chunk);
+    }
+
+    /// inserts `chunk` right before `IEND`, or last without one, and returns
+    /// its index; anything after `IEND` is dropped by other tools
+    pub fn insert_before_end(&mut self, chunk: Chunk) -> usize {
+        let end = self.chunks.iter().position(|ch| ch.chunk_type().bytes() == *b"IEND");
+        let idx = end.unwrap_or(self.chunks.len());
+        self.chunks.insert(idx, chunk);
+        idx
+    }
+
     pub fn remove_chunk(&mut self, chunk_type: &str) -> Result<Chunk> {
         let idx = self
             .chunks
             .iter()
             .zip(0..self.chunks.len())
-            .find(|(chunk, _)| chunk.chunk_type().to_string() == chunk_type)
+            .find(|(chunk, _)| chunk.chunk_type().bytes() == chunk_type.as_bytes())
             .map(|tup| tup.1);
 
         match idx {
@@ -57,6 +73,78 @@ impl Png {
         }
     }
 
+    pub fn remove_chunk_at(&mut self, index: usize) -> Result<Chunk> {
+        if index >= self.chunks.len() {
+            return Err(Box::new(PngError { msg: "chunk index out of range" }));
+        }
+        Ok(self.chunks.remove(index))
+    }
+
+    /// replaces the first chunk of the same type, returning the old one
+    pub fn replace_chunk(&mut self, chunk: Chunk) -> Result<Chunk> {
+        match self.chunks.iter().position(|ch| ch.chunk_type() == chunk.chunk_type()) {
+            Some(idx) => Ok(std::mem::replace(&mut self.chunks[idx], chunk)),
+            None => Err(Box::new(PngError {
+                msg: "chunk type not found",
+            })),
+        }
+    }
+
+    /// concatenated data of all `IDAT` chunks
+    pub fn image_data(&self) -> Vec<u8> {
+        self.chunks
+            .iter()
+            .filter(|ch| ch.chunk_type().bytes() == *b"IDAT")
+            .flat_map(|ch| ch.data().iter().copied())
+            .collect()
+    }
+
+    /// replaces all `IDAT` chunks with a single one holding `data`
+    pub fn set_image_data(&mut self, data: Vec<u8>) -> Result<()> {
+        self.set_image_data_split(data, usize::MAX)
+    }
+
+    /// replaces all `IDAT` chunks with chunks of at most `max_len` bytes
+    pub fn set_image_data_split(&mut self, data: Vec<u8>, max_len: usize) -> Result<()> {
+        let idat = ChunkType::try_from(*b"IDAT")?;
+        let first = self
+            .chunks
+            .iter()
+            .position(|ch| *ch.chunk_type() == idat)
+            .ok_or(PngError { msg: "png has no IDAT chunk" })?;
+        self.chunks.retain(|ch| *ch.chunk_type() != idat);
+        let max_len = max_len.clamp(1, MAX_CHUNK_SIZE as usize);
+        let chunks: Vec<Chunk> = if data.is_empty() {
+            vec![Chunk::new(idat, data)]
+        } else {
+            data.chunks(max_len).map(|part| Chunk::new(idat, part.to_vec())).collect()
+        };
+        self.chunks.splice(first..first, chunks);
+
+        Ok(())
+    }
+
+    /// gives the first chunk of type `old` the type `new`, recomputing its crc
+    pub fn rename_chunk(&mut self, old: &str, new: ChunkType) -> Result<()> {
+        let idx = self
+            .chunks
+            .iter()
+            .position(|ch| ch.chunk_type().bytes() == old.as_bytes())
+            .ok_or(PngError { msg: "chunk type not found" })?;
+        let chunk = self.chunks.remove(idx);
+        self.chunks.insert(idx, Chunk::new(new, chunk.into_data()));
+
+        Ok(())
+    }
+
+    pub fn into_chunks(self) -> Vec<Chunk> {
+        self.chunks
+    }
+
+    pub fn retain_chunks<F: FnMut(&Chunk) -> bool>(&mut self, f: F) {
+        self.chunks.retain(f);
+    }
+
     pub fn header(&self) -> &[u8; 8] {
         &self.signature
     }
@@ -68,7 +156,21 @@ impl Png {
     pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&Chunk> {
         self.chunks
             .iter()
-            .find(|chunk| chunk.chunk_type().to_string() == chunk_type)
+            .find(|chunk| chunk.chunk_type().bytes() == chunk_type.as_bytes())
+    }
+
+    /// picks an ancillary, private, safe-to-copy chunk type that is not a
+    /// standard type and is not used by any chunk in this png yet
+    pub fn unused_private_type(&self) -> Option<ChunkType> {
+        (b'A'..=b'Z')
+            .flat_map(|third| (b'a'..=b'z').map(move |fourth| [b'p', b'm', third, fourth]))
+            .filter_map(|bytes| ChunkType::try_from(bytes).ok())
+            .filter(|ct| !ct.is_critical() && !ct.is_public() && ct.is_safe_to_copy())
+            .find(|ct| ct.is_valid() && !ct.is_standard() && !self.has_chunk_type(ct))
+    }
+
+    fn has_chunk_type(&self, chunk_type: &ChunkType) -> bool {
+        self.chunks.iter().any(|chunk| chunk.chunk_type() == chunk_type)
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
@@ -79,36 +181,129 @@ impl Png {
     }
 }
 
-impl TryFrom<&[u8]> for Png {
-    type Error = Error;
+/// a png borrowing all chunk data from the buffer it was parsed from
+pub struct PngRef<'a> {
+    chunks: Vec<ChunkRef<'a>>,
+}
 
-    fn try_from(value: &[u8]) -> Result<Self> {
-        let mut buf = BufReader::new(value);
-        let mut sig = [0; 8];
-        buf.read_exact(&mut sig)?;
-        if sig != Png::STANDARD_HEADER {
+impl<'a> PngRef<'a> {
+    pub fn chunks(&self) -> &[ChunkRef<'a>] {
+        &self.chunks
+    }
+
+    pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&ChunkRef<'a>> {
+        self.chunks
+            .iter()
+            .find(|chunk| chunk.chunk_type().bytes() == chunk_type.as_bytes())
+    }
+
+    pub fn to_png(&self) -> Png {
+        Png::from_chunks(self.chunks.iter().map(|ch| ch.to_chunk()).collect())
+    }
+
+    /// concatenated `IDAT` data, read without copying it
+    pub fn idat_reader(&self) -> impl Read + '_ {
+        IdatReader { chunks: self.chunks.iter(), current: &[] }
+    }
+
+    fn parse_chunks(value: &'a [u8], limits: &Limits) -> Result<Self> {
+        limits.check_total_size(value.len())?;
+        if value.len() < Png::STANDARD_HEADER.len() || value[..8] != Png::STANDARD_HEADER {
             return Err(Box::new(PngError {
                 msg: "invalid png signature",
             }));
         }
 
-        let mut chunks: Vec<Chunk> = Vec::new();
-        let mut len = [0; 4];
-        let mut chk_type = [0; 4];
-        let mut crc = [0; 4];
-        while let Ok(()) = buf.read_exact(&mut len) {
-            buf.read_exact(&mut chk_type)?;
-            let length = u32::from_be_bytes(len);
-            let mut data = vec![0; length as usize];
-            buf.read_exact(&mut data)?;
-            buf.read_exact(&mut crc)?;
-            chunks.push(Chunk::new(ChunkType::try_from(chk_type).unwrap(), data));
+        let mut chunks = Vec::new();
+        let mut rest = &value[8..];
+        while !rest.is_empty() {
+            let (chunk, len) = ChunkRef::parse(rest)?;
+            limits.check_chunk(chunks.len(), chunk.length())?;
+            chunks.push(chunk);
+            rest = &rest[len..];
         }
 
-        Ok(Png {
-            chunks,
-            ..Png::template_png()
-        })
+        Ok(PngRef { chunks })
+    }
+
+    /// parses untrusted input: on top of the structural limits every zlib
+    /// stream is inflated, without keeping the output, to bound its size
+    pub fn parse_with_limits(value: &'a [u8], limits: &Limits) -> Result<Self> {
+        let png = PngRef::parse_chunks(value, limits)?;
+        for chunk in png.chunks.iter() {
+            if let Some(stream) = compressed_data(chunk) {
+                limits.check_inflated_size(stream)?;
+            }
+        }
+        if png.chunk_by_type("IDAT").is_some() {
+            limits.check_inflated_size(png.idat_reader())?;
+        }
+
+        Ok(png)
+    }
+}
+
+/// reads the data of one `IDAT` chunk after the other; flat rather than a
+/// chain of readers, which would nest once per chunk
+struct IdatReader<'r, 'a> {
+    chunks: std::slice::Iter<'r, ChunkRef<'a>>,
+    current: &'a [u8],
+}
+
+impl Read for IdatReader<'_, '_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.current.is_empty() {
+            match self.chunks.next() {
+                Some(ch) if ch.chunk_type().bytes() == *b"IDAT" => self.current = ch.data(),
+                Some(_) => {}
+                None => return Ok(0),
+            }
+        }
+        self.current.read(buf)
+    }
+}
+
+/// zlib stream held by `iCCP`, `zTXt` and compressed `iTXt` chunks
+fn compressed_data<'a>(chunk: &ChunkRef<'a>) -> Option<&'a [u8]> {
+    let data = chunk.data();
+    let after_keyword = data.iter().position(|&b| b == 0)? + 1;
+    match &chunk.chunk_type().bytes() {
+        b"iCCP" | b"zTXt" => data.get(after_keyword + 1..),
+        b"iTXt" if data.get(after_keyword) == Some(&1) => {
+            // skip the compression flag and method, then language and translated keyword
+            let mut rest = data.get(after_keyword + 2..)?;
+            for _ in 0..2 {
+                rest = &rest[rest.iter().position(|&b| b == 0)? + 1..];
+            }
+            Some(rest)
+        }
+        _ => None,
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for PngRef<'a> {
+    type Error = Error;
+
+    /// checks the structural limits of [`Limits::default`], use
+    /// [`PngRef::parse_with_limits`] for untrusted input
+    fn try_from(value: &'a [u8]) -> Result<Self> {
+        PngRef::parse_chunks(value, &Limits::default())
+    }
+}
+
+impl std::fmt::Display for PngRef<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let out: Vec<_> = self.chunks.iter().map(|ch| ch.to_string()).collect();
+
+        write!(f, "{}", out.join(" "))
+    }
+}
+
+impl TryFrom<&[u8]> for Png {
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        Ok(PngRef::try_from(value)?.to_png())
     }
 }
 
@@ -249,6 +444,31 @@ mod tests {
         assert!(chunk.is_none());
     }
 
+    #[test]
+    fn test_rename_chunk() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap());
+        png.rename_chunk("TeSt", ChunkType::from_str("TeST").unwrap()).unwrap();
+
+        let chunk = png.chunk_by_type("TeST").unwrap();
+        assert_eq!(chunk.data(), b"Message");
+        assert!(Chunk::try_from(&chunk.as_bytes()[..]).is_ok());
+        assert!(png.rename_chunk("TeSt", ChunkType::from_str("TeST").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_unused_private_type() {
+        let mut png = testing_png();
+        let chunk_type = png.unused_private_type().unwrap();
+        assert!(chunk_type.is_valid());
+        assert!(!chunk_type.is_critical());
+        assert!(!chunk_type.is_public());
+        assert!(chunk_type.is_safe_to_copy());
+
+        png.append_chunk(Chunk::new(chunk_type, Vec::new()));
+        assert_ne!(png.unused_private_type().unwrap(), chunk_type);
+    }
+
     #[test]
     fn test_png_from_image_file() {
         let png = Png::try_from(&PNG_FILE[..]);
@@ -263,6 +483,89 @@ mod tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn test_png_ref_borrows_file() {
+        let png = PngRef::try_from(&PNG_FILE[..]).unwrap();
+        assert_eq!(png.chunks().len(), 7);
+
+        let idat = png.chunk_by_type("IDAT").unwrap();
+        let offset = idat.data().as_ptr() as usize - PNG_FILE.as_ptr() as usize;
+        assert_eq!(&PNG_FILE[offset..offset + idat.data().len()], idat.data());
+        assert!(png.chunks().iter().all(|ch| ch.is_crc_valid()));
+        assert_eq!(png.to_string(), Png::try_from(&PNG_FILE[..]).unwrap().to_string());
+    }
+
+    #[test]
+    fn test_parse_with_limits() {
+        assert!(PngRef::parse_with_limits(&PNG_FILE[..], &Limits::default()).is_ok());
+
+        let limits = Limits { max_chunks: 3, ..Limits::default() };
+        assert!(PngRef::parse_with_limits(&PNG_FILE[..], &limits).is_err());
+
+        let limits = Limits { max_chunk_size: 1000, ..Limits::default() };
+        assert!(PngRef::parse_with_limits(&PNG_FILE[..], &limits).is_err());
+
+        let limits = Limits { max_total_size: 1000, ..Limits::default() };
+        assert!(PngRef::parse_with_limits(&PNG_FILE[..], &limits).is_err());
+
+        let limits = Limits { max_decompressed_size: 1000, ..Limits::default() };
+        assert!(PngRef::parse_with_limits(&PNG_FILE[..], &limits).is_err());
+    }
+
+    #[test]
+    fn test_huge_length_does_not_allocate() {
+        // 20 bytes claiming a chunk of almost 4 GB
+        let mut bytes = Png::STANDARD_HEADER.to_vec();
+        bytes.extend_from_slice(&[0xff, 0xff, 0xff, 0xf0, b'I', b'D', b'A', b'T', 0, 0, 0, 0]);
+        assert!(Png::try_from(&bytes[..]).is_err());
+        assert!(PngRef::parse_with_limits(&bytes, &Limits::default()).is_err());
+    }
+
+    #[test]
+    fn test_non_letter_chunk_type() {
+        let mut bytes = Png::STANDARD_HEADER.to_vec();
+        bytes.extend_from_slice(&[0, 0, 0, 0, 0xff, 0xfe, 0x41, 0x41, 0, 0, 0, 0]);
+        assert!(PngRef::parse_with_limits(&bytes, &Limits::default()).is_err());
+        assert!(Png::try_from(&bytes[..]).is_err());
+    }
+
+    #[test]
+    fn test_compressed_text_bomb() {
+        let mut data = b"bomb\0\0".to_vec();
+        data.extend(crate::image::zlib_compress(&vec![b'a'; 100_000]).unwrap());
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.append_chunk(Chunk::new(ChunkType::from_str("zTXt").unwrap(), data));
+        let bytes = png.as_bytes();
+
+        let limits = Limits { max_decompressed_size: 50_000, ..Limits::default() };
+        assert!(PngRef::parse_with_limits(&bytes, &Limits::default()).is_ok());
+        assert!(PngRef::parse_with_limits(&bytes, &limits).is_err());
+    }
+
+    #[test]
+    fn test_many_idat_chunks() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let data = png.image_data();
+        let copy = |chunk_type| Chunk::try_from(&png.chunk_by_type(chunk_type).unwrap().as_bytes()[..]).unwrap();
+        let mut chunks = vec![copy("IHDR")];
+        chunks.extend((0..100_000).map(|_| Chunk::new(ChunkType::from_str("IDAT").unwrap(), Vec::new())));
+        chunks.push(Chunk::new(ChunkType::from_str("IDAT").unwrap(), data.clone()));
+        chunks.push(copy("IEND"));
+        let bytes = Png::from_chunks(chunks).as_bytes();
+
+        let png = PngRef::parse_with_limits(&bytes, &Limits::default()).unwrap();
+        let mut read = Vec::new();
+        png.idat_reader().read_to_end(&mut read).unwrap();
+        assert_eq!(read, data);
+        assert!(png.decode_pixels().is_ok());
+    }
+
+    #[test]
+    fn test_png_ref_truncated() {
+        assert!(PngRef::try_from(&PNG_FILE[..PNG_FILE.len() - 3]).is_err());
+        assert!(PngRef::try_from(&PNG_FILE[..4]).is_err());
+    }
+
     #[test]
     fn test_png_trait_impls() {
         let chunk_bytes: Vec<u8> = testing_chunks()
@@ -0,0 +1,140 @@
+//! the `pngme` python extension module, enabled by the `python` feature
+
+use std::path::PathBuf;
+
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+
+use crate::carrier::{Carrier, CarrierError as RustCarrierError, Format};
+use crate::chunk::ChunkError as RustChunkError;
+use crate::chunk_type::ChunkTypeError as RustChunkTypeError;
+use crate::payload::{Compression, Options, PayloadError as RustPayloadError};
+use crate::png::{Png, PngError as RustPngError};
+use crate::{commands, optimize, Error};
+
+create_exception!(pngme, PngmeError, PyException, "base class of every error raised by pngme");
+create_exception!(pngme, PngError, PngmeError, "the file is not a png pngme can work with");
+create_exception!(pngme, ChunkError, PngmeError, "a chunk is malformed");
+create_exception!(pngme, ChunkTypeError, PngmeError, "a chunk type is not four ascii letters");
+create_exception!(pngme, PayloadError, PngmeError, "a message cannot be stored or read back");
+create_exception!(pngme, CarrierError, PngmeError, "the file cannot hold the message");
+
+/// raises the python exception matching the rust error, `OSError` for io errors
+fn to_py(e: Error) -> PyErr {
+    let msg = e.to_string();
+    if e.is::<RustPngError>() {
+        PngError::new_err(msg)
+    } else if e.is::<RustChunkError>() {
+        ChunkError::new_err(msg)
+    } else if e.is::<RustChunkTypeError>() {
+        ChunkTypeError::new_err(msg)
+    } else if e.is::<RustPayloadError>() {
+        PayloadError::new_err(msg)
+    } else if e.is::<RustCarrierError>() {
+        CarrierError::new_err(msg)
+    } else {
+        match e.downcast::<std::io::Error>() {
+            Ok(e) => (*e).into(),
+            Err(e) => PngmeError::new_err(e.to_string()),
+        }
+    }
+}
+
+/// messages can be given as `str` or `bytes`
+#[derive(FromPyObject)]
+enum Message {
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+impl Message {
+    fn into_bytes(self) -> Vec<u8> {
+        match self {
+            Message::Text(text) => text.into_bytes(),
+            Message::Bytes(bytes) => bytes,
+        }
+    }
+}
+
+/// a png held in memory, edited in place and written back with `to_bytes`
+#[pyclass(name = "Png", module = "pngme")]
+pub struct PyPng {
+    png: Png,
+}
+
+#[pymethods]
+impl PyPng {
+    #[new]
+    fn new(data: &[u8]) -> PyResult<Self> {
+        Ok(PyPng { png: Png::try_from(data).map_err(to_py)? })
+    }
+
+    #[staticmethod]
+    fn open(path: PathBuf) -> PyResult<Self> {
+        PyPng::new(&std::fs::read(path)?)
+    }
+
+    /// every chunk as a `(type, data)` tuple, in file order
+    #[getter]
+    fn chunks(&self) -> Vec<(String, Vec<u8>)> {
+        self.png.chunks().iter().map(|ch| (ch.chunk_type().to_string(), ch.data().to_vec())).collect()
+    }
+
+    /// hides `message` like `pngme encode` and returns the chunk type used
+    #[pyo3(signature = (message, chunk_type=None, ecc=None, compress=None))]
+    fn encode(&mut self, message: Message, chunk_type: Option<String>, ecc: Option<u8>, compress: Option<&str>) -> PyResult<String> {
+        let compress = match compress {
+            None => None,
+            Some("zlib") => Some(Compression::Zlib),
+            Some("zstd") => Some(Compression::Zstd),
+            Some(_) => return Err(PayloadError::new_err("compress must be \"zlib\" or \"zstd\"")),
+        };
+        let options = Options { ecc, sign: None, compress };
+        commands::encode_into(&mut self.png, chunk_type, &message.into_bytes(), &options).map_err(to_py)
+    }
+
+    /// the message in `chunk_type`, or in the chunk picked by `encode`
+    #[pyo3(signature = (chunk_type=None))]
+    fn decode(&self, chunk_type: Option<String>) -> PyResult<Vec<u8>> {
+        let (payload, _) = commands::find_payload(Format::Png, &self.png.segments(), chunk_type).map_err(to_py)?;
+        Ok(payload.message)
+    }
+
+    /// removes the first chunk of the given type like `pngme remove` and returns its data
+    fn remove(&mut self, chunk_type: &str) -> PyResult<Vec<u8>> {
+        commands::remove_from(&mut self.png, chunk_type).map_err(to_py)
+    }
+
+    /// drops ancillary chunks like `pngme optimize --strip`, returns their types
+    #[pyo3(signature = (keep=Vec::new()))]
+    fn strip(&mut self, keep: Vec<String>) -> Vec<String> {
+        optimize::strip(&mut self.png, &keep)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.png.as_bytes()
+    }
+
+    fn save(&self, path: PathBuf) -> PyResult<()> {
+        Ok(std::fs::write(path, self.png.as_bytes())?)
+    }
+
+    fn __repr__(&self) -> String {
+        let types: Vec<_> = self.png.chunks().iter().map(|ch| ch.chunk_type().to_string()).collect();
+        format!("Png({})", types.join(" "))
+    }
+}
+
+#[pymodule]
+fn pngme(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    let py = m.py();
+    m.add_class::<PyPng>()?;
+    m.add("PngmeError", py.get_type::<PngmeError>())?;
+    m.add("PngError", py.get_type::<PngError>())?;
+    m.add("ChunkError", py.get_type::<ChunkError>())?;
+    m.add("ChunkTypeError", py.get_type::<ChunkTypeError>())?;
+    m.add("PayloadError", py.get_type::<PayloadError>())?;
+    m.add("CarrierError", py.get_type::<CarrierError>())?;
+    Ok(())
+}
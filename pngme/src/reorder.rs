@@ -0,0 +1,169 @@
+use crate::chunk::Chunk;
+use crate::png::{Png, PngError};
+use crate::Result;
+
+/// predefined ways to reorder chunks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Rule {
+    /// moves private ancillary chunks found after the image data in front of it
+    PrivateBeforeIdat,
+    /// sorts tEXt, zTXt and iTXt chunks by keyword, in the places they already hold
+    SortText,
+}
+
+pub enum Order {
+    Rule(Rule),
+    /// the listed chunk types in this order, in the places they already hold;
+    /// chunks of other types do not move
+    Explicit(Vec<String>),
+}
+
+const TEXT_CHUNKS: [&str; 3] = ["tEXt", "zTXt", "iTXt"];
+const BEFORE_PLTE: [&str; 5] = ["cHRM", "gAMA", "iCCP", "sBIT", "sRGB"];
+const AFTER_PLTE: [&str; 3] = ["bKGD", "hIST", "tRNS"];
+const BEFORE_IDAT: [&str; 2] = ["pHYs", "sPLT"];
+
+/// ordering rules of the png specification broken by `types`
+pub(crate) fn violations(types: &[String]) -> Vec<&'static str> {
+    let first = |names: &[&str]| types.iter().position(|t| names.contains(&t.as_str()));
+    let last = |names: &[&str]| types.iter().rposition(|t| names.contains(&t.as_str()));
+    let (plte, idat, idat_end) = (first(&["PLTE"]), first(&["IDAT"]), last(&["IDAT"]));
+    let before = |a: Option<usize>, b: Option<usize>| match (a, b) {
+        (Some(a), Some(b)) => a < b,
+        _ => true,
+    };
+
+    let mut broken = Vec::new();
+    if types.first().map(String::as_str) != Some("IHDR") {
+        broken.push("IHDR must be the first chunk");
+    }
+    if types.last().map(String::as_str) != Some("IEND") {
+        broken.push("IEND must be the last chunk");
+    }
+    if !before(plte, idat) {
+        broken.push("PLTE must precede IDAT");
+    }
+    if let (Some(start), Some(end)) = (idat, idat_end) {
+        if types[start..=end].iter().any(|t| t != "IDAT") {
+            broken.push("IDAT chunks must be consecutive");
+        }
+    }
+    if !before(last(&BEFORE_PLTE), plte.or(idat)) {
+        broken.push("cHRM, gAMA, iCCP, sBIT and sRGB must precede PLTE and IDAT");
+    }
+    if !before(plte, first(&AFTER_PLTE)) || !before(last(&AFTER_PLTE), idat) {
+        broken.push("bKGD, hIST and tRNS must follow PLTE and precede IDAT");
+    }
+    if !before(last(&BEFORE_IDAT), idat) {
+        broken.push("pHYs and sPLT must precede IDAT");
+    }
+
+    broken
+}
+
+/// sorts the chunks with a key by `key`, each staying in one of the places
+/// those chunks held; the other chunks do not move
+fn sort_in_place<K: Ord>(chunks: Vec<Chunk>, key: impl Fn(&Chunk) -> Option<K>) -> Vec<Chunk> {
+    let slots: Vec<usize> = (0..chunks.len()).filter(|&i| key(&chunks[i]).is_some()).collect();
+    let mut chunks: Vec<Option<Chunk>> = chunks.into_iter().map(Some).collect();
+    let mut selected: Vec<Chunk> = slots.iter().filter_map(|&i| chunks[i].take()).collect();
+    selected.sort_by_key(|ch| key(ch));
+    for (slot, chunk) in slots.into_iter().zip(selected) {
+        chunks[slot] = Some(chunk);
+    }
+
+    chunks.into_iter().flatten().collect()
+}
+
+fn private_before_idat(chunks: Vec<Chunk>) -> Result<Vec<Chunk>> {
+    let idat = chunks
+        .iter()
+        .position(|ch| ch.chunk_type().bytes() == *b"IDAT")
+        .ok_or(PngError { msg: "png has no IDAT chunk" })?;
+    let (head, tail): (Vec<_>, Vec<_>) = chunks.into_iter().enumerate().partition(|(i, _)| *i < idat);
+    let (moved, rest): (Vec<_>, Vec<_>) = tail
+        .into_iter()
+        .partition(|(_, ch)| !ch.chunk_type().is_critical() && !ch.chunk_type().is_public());
+
+    Ok(head.into_iter().chain(moved).chain(rest).map(|(_, ch)| ch).collect())
+}
+
+fn text_keyword(chunk: &Chunk) -> Option<Vec<u8>> {
+    if !TEXT_CHUNKS.contains(&chunk.chunk_type().to_string().as_str()) {
+        return None;
+    }
+    chunk.data().split(|&b| b == 0).next().map(<[u8]>::to_vec)
+}
+
+/// reorders the chunks of `png`, refusing orders that break a rule of the
+/// png specification the original order kept
+pub fn reorder(png: Png, order: &Order) -> Result<Png> {
+    let types = |chunks: &[Chunk]| -> Vec<String> { chunks.iter().map(|ch| ch.chunk_type().to_string()).collect() };
+    let chunks = png.into_chunks();
+    let before = violations(&types(&chunks));
+
+    let chunks = match order {
+        Order::Rule(Rule::PrivateBeforeIdat) => private_before_idat(chunks)?,
+        Order::Rule(Rule::SortText) => sort_in_place(chunks, text_keyword),
+        Order::Explicit(list) => sort_in_place(chunks, |ch| list.iter().position(|t| *t == ch.chunk_type().to_string())),
+    };
+
+    if let Some(&msg) = violations(&types(&chunks)).iter().find(|msg| !before.contains(msg)) {
+        return Err(Box::new(PngError { msg }));
+    }
+
+    Ok(Png::from_chunks(chunks))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn png(chunks: &[(&str, &str)]) -> Png {
+        let chunks = chunks
+            .iter()
+            .map(|(chunk_type, data)| Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.as_bytes().to_vec()))
+            .collect();
+        Png::from_chunks(chunks)
+    }
+
+    fn types(png: &Png) -> Vec<String> {
+        png.chunks().iter().map(|ch| ch.chunk_type().to_string()).collect()
+    }
+
+    #[test]
+    fn test_private_before_idat() {
+        let original = png(&[("IHDR", ""), ("IDAT", ""), ("ruSt", ""), ("tEXt", ""), ("IEND", ""), ("pmAa", "")]);
+        let reordered = reorder(original, &Order::Rule(Rule::PrivateBeforeIdat)).unwrap();
+        assert_eq!(types(&reordered), ["IHDR", "ruSt", "pmAa", "IDAT", "tEXt", "IEND"]);
+    }
+
+    #[test]
+    fn test_sort_text() {
+        let original = png(&[("IHDR", ""), ("tEXt", "b\0"), ("IDAT", ""), ("iTXt", "a\0"), ("IEND", "")]);
+        let reordered = reorder(original, &Order::Rule(Rule::SortText)).unwrap();
+        let data: Vec<&[u8]> = reordered.chunks().iter().map(|ch| ch.data()).collect();
+        assert_eq!(data[1], b"a\0");
+        assert_eq!(data[3], b"b\0");
+    }
+
+    #[test]
+    fn test_explicit_order() {
+        let original = png(&[("IHDR", ""), ("tIME", ""), ("gAMA", ""), ("IDAT", ""), ("IEND", "")]);
+        let order = Order::Explicit(vec![String::from("gAMA"), String::from("tIME")]);
+        assert_eq!(types(&reorder(original, &order).unwrap()), ["IHDR", "gAMA", "tIME", "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn test_refuses_breaking_moves() {
+        let original = png(&[("IHDR", ""), ("PLTE", ""), ("IDAT", ""), ("IEND", "")]);
+        let order = Order::Explicit(vec![String::from("IDAT"), String::from("PLTE")]);
+        assert!(reorder(original, &order).is_err());
+
+        let original = png(&[("IHDR", ""), ("tRNS", ""), ("PLTE", ""), ("IDAT", ""), ("IEND", "")]);
+        let order = Order::Explicit(vec![String::from("tIME")]);
+        assert!(reorder(original, &order).is_ok());
+    }
+}
@@ -0,0 +1,234 @@
+use std::fmt;
+
+use crate::carrier::{self, carrier_error, Carrier, Format, Segment};
+use crate::{Error, Result};
+
+const HEADER_LEN: usize = 12;
+
+/// chunks a reader needs to decode the file, by form type
+fn essential_chunks(form: &[u8; 4]) -> &'static [&'static [u8; 4]] {
+    match form {
+        b"WAVE" => &[b"fmt ", b"data"],
+        b"WEBP" => &[b"VP8 ", b"VP8L", b"VP8X", b"ALPH", b"ANIM", b"ANMF"],
+        _ => &[],
+    }
+}
+
+pub(crate) fn tag(id: &[u8; 4]) -> String {
+    String::from_utf8_lossy(id).into_owned()
+}
+
+/// chunk id and data borrowed from the parsed file
+type RawChunk<'a> = ([u8; 4], &'a [u8]);
+
+/// splits a RIFF file into its form type, its top level chunks and whatever
+/// follows the size the header declares
+pub(crate) fn parse_chunks(bytes: &[u8]) -> Result<([u8; 4], Vec<RawChunk<'_>>, &[u8])> {
+    if bytes.len() < HEADER_LEN || &bytes[..4] != b"RIFF" {
+        return Err(carrier_error("missing RIFF header"));
+    }
+    let size = u32::from_le_bytes(bytes[4..8].try_into()?) as usize;
+    let end = size
+        .checked_add(8)
+        .filter(|&end| end <= bytes.len() && end >= HEADER_LEN)
+        .ok_or_else(|| carrier_error("RIFF size does not match the file"))?;
+    let form = bytes[8..12].try_into()?;
+
+    let mut chunks = Vec::new();
+    let mut pos = HEADER_LEN;
+    while pos < end {
+        if end - pos < 8 {
+            return Err(carrier_error("truncated RIFF chunk header"));
+        }
+        let id: [u8; 4] = bytes[pos..pos + 4].try_into()?;
+        let len = u32::from_le_bytes(bytes[pos + 4..pos + 8].try_into()?) as usize;
+        let data = bytes
+            .get(pos + 8..)
+            .and_then(|rest| rest.get(..len))
+            .filter(|_| pos + 8 + len <= end)
+            .ok_or_else(|| carrier_error("RIFF chunk is longer than the file"))?;
+        chunks.push((id, data));
+        // chunks are padded to an even length, the last pad byte may be missing;
+        // serializing writes a zero there, anything else would be lost
+        if len % 2 == 1 && pos + 8 + len < end && bytes[pos + 8 + len] != 0 {
+            return Err(carrier_error("RIFF pad byte is not zero"));
+        }
+        pos = (pos + 8 + len + len % 2).min(end);
+    }
+
+    Ok((form, chunks, &bytes[end..]))
+}
+
+/// a RIFF container such as WAV or WebP
+pub struct Riff {
+    form: [u8; 4],
+    chunks: Vec<([u8; 4], Vec<u8>)>,
+    /// bytes after the declared RIFF size, kept as they are
+    trailing: Vec<u8>,
+}
+
+impl Riff {
+    pub fn form(&self) -> &[u8; 4] {
+        &self.form
+    }
+}
+
+impl TryFrom<&[u8]> for Riff {
+    type Error = Error;
+
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        let (form, chunks, trailing) = parse_chunks(bytes)?;
+        Ok(Riff {
+            form,
+            chunks: chunks.into_iter().map(|(id, data)| (id, data.to_vec())).collect(),
+            trailing: trailing.to_vec(),
+        })
+    }
+}
+
+impl fmt::Display for Riff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let out: Vec<_> = self.segments().iter().map(|seg| seg.to_string()).collect();
+
+        write!(f, "{}", out.join(" "))
+    }
+}
+
+impl Carrier for Riff {
+    fn format(&self) -> Format {
+        Format::Riff
+    }
+
+    fn segments(&self) -> Vec<Segment<'_>> {
+        self.chunks
+            .iter()
+            .map(|(id, data)| Segment { tag: tag(id), data })
+            .collect()
+    }
+
+    fn check_tag(&self, tag: &str) -> Result<()> {
+        let id: [u8; 4] = tag
+            .as_bytes()
+            .try_into()
+            .map_err(|_| carrier_error("RIFF chunk ids are 4 bytes long"))?;
+        if !id.iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+            return Err(carrier_error("RIFF chunk ids are printable ascii"));
+        }
+        if &id == b"RIFF" || &id == b"LIST" || essential_chunks(&self.form).contains(&&id) {
+            return Err(carrier_error("chunk id is needed to read the file"));
+        }
+
+        Ok(())
+    }
+
+    fn free_tag(&self) -> Option<String> {
+        carrier::unused_tag(|tag| self.chunks.iter().any(|(id, _)| id == tag.as_bytes()))
+    }
+
+    fn insert(&mut self, tag: &str, data: Vec<u8>) -> Result<()> {
+        let id = tag
+            .as_bytes()
+            .try_into()
+            .map_err(|_| carrier_error("RIFF chunk ids are 4 bytes long"))?;
+        if u32::try_from(data.len()).is_err() {
+            return Err(carrier_error("data does not fit in a RIFF chunk"));
+        }
+        self.chunks.push((id, data));
+
+        Ok(())
+    }
+
+    fn remove(&mut self, tag: &str) -> Result<Vec<u8>> {
+        let idx = self
+            .chunks
+            .iter()
+            .position(|(id, _)| id == tag.as_bytes())
+            .ok_or_else(|| carrier_error("chunk id not found"))?;
+
+        Ok(self.chunks.remove(idx).1)
+    }
+
+    fn retain(&mut self, f: &mut dyn FnMut(&Segment) -> bool) {
+        self.chunks.retain(|(id, data)| f(&Segment { tag: tag(id), data }));
+    }
+
+    fn as_bytes(&self) -> Vec<u8> {
+        let mut body = self.form.to_vec();
+        for (id, data) in &self.chunks {
+            body.extend_from_slice(id);
+            body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            body.extend_from_slice(data);
+            if data.len() % 2 == 1 {
+                body.push(0);
+            }
+        }
+
+        [&b"RIFF"[..], &(body.len() as u32).to_le_bytes(), &body, &self.trailing].concat()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wave() -> Vec<u8> {
+        let mut bytes = b"RIFF\0\0\0\0WAVE".to_vec();
+        bytes.extend_from_slice(b"fmt \x03\0\0\0abc\0");
+        bytes.extend_from_slice(b"data\x04\0\0\0\x01\x02\x03\x04");
+        let size = (bytes.len() - 8) as u32;
+        bytes[4..8].copy_from_slice(&size.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn test_parse_and_serialize() {
+        let riff = Riff::try_from(&wave()[..]).unwrap();
+        assert_eq!(riff.form(), b"WAVE");
+        assert_eq!(riff.segments()[0], Segment { tag: String::from("fmt "), data: b"abc" });
+        assert_eq!(riff.as_bytes(), wave());
+    }
+
+    #[test]
+    fn test_insert_and_remove() {
+        let mut riff = Riff::try_from(&wave()[..]).unwrap();
+        let tag = riff.free_tag().unwrap();
+        riff.insert(&tag, b"hidden".to_vec()).unwrap();
+
+        let mut reparsed = Riff::try_from(&riff.as_bytes()[..]).unwrap();
+        assert_eq!(reparsed.segments().len(), 3);
+        assert_eq!(reparsed.remove(&tag).unwrap(), b"hidden");
+        assert_eq!(reparsed.as_bytes(), wave());
+    }
+
+    #[test]
+    fn test_check_tag() {
+        let riff = Riff::try_from(&wave()[..]).unwrap();
+        assert!(riff.check_tag("note").is_ok());
+        assert!(riff.check_tag("data").is_err());
+        assert!(riff.check_tag("toolong").is_err());
+    }
+
+    #[test]
+    fn test_bytes_after_riff_size_are_kept() {
+        let bytes = [wave(), b"id3 tag".to_vec()].concat();
+        let mut riff = Riff::try_from(&bytes[..]).unwrap();
+        assert_eq!(riff.as_bytes(), bytes);
+
+        riff.insert("note", b"hidden".to_vec()).unwrap();
+        assert!(riff.as_bytes().ends_with(b"id3 tag"));
+        assert_eq!(Riff::try_from(&riff.as_bytes()[..]).unwrap().segments().len(), 3);
+    }
+
+    #[test]
+    fn test_non_zero_pad_byte() {
+        let mut bytes = wave();
+        bytes[23] = 7;
+        assert!(Riff::try_from(&bytes[..]).is_err());
+    }
+
+    #[test]
+    fn test_truncated_file() {
+        let bytes = wave();
+        assert!(Riff::try_from(&bytes[..bytes.len() - 2]).is_err());
+    }
+}
@@ -0,0 +1,293 @@
+use std::fmt;
+use std::fs;
+use std::io::{BufRead, Write};
+use std::str::FromStr;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::history::Recorder;
+use crate::png::{Png, PngRef};
+use crate::{commands, Result};
+
+const HELP: &str = "\
+ls                  lists the chunks with their index
+cat <idx>           prints the data of a chunk as text
+hexdump <idx>       prints the data of a chunk as hex
+rm <idx>            removes a chunk
+add <type> <text>   adds a chunk before IEND
+mv <from> <to>      moves a chunk to another index
+validate            checks crcs, chunk types and ordering
+undo                reverts the last edit
+save [path]         writes the file, in place unless a path is given
+quit                leaves, twice if there are unsaved edits";
+
+#[derive(Debug)]
+pub struct ShellError {
+    msg: String,
+}
+
+impl fmt::Display for ShellError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "error in shell command: {}", self.msg)
+    }
+}
+
+impl std::error::Error for ShellError {}
+
+fn usage(msg: &str) -> Box<ShellError> {
+    Box::new(ShellError { msg: msg.to_string() })
+}
+
+/// a png loaded once and edited in memory until `save`
+pub struct Shell {
+    path: String,
+    png: Png,
+    /// the file before every edit, for `undo`
+    history: Vec<Vec<u8>>,
+    /// the file as loaded or last saved, what a history entry starts from
+    saved: Vec<u8>,
+    recorder: Option<Recorder>,
+    unsaved: bool,
+    quit_warned: bool,
+}
+
+/// classic 16 bytes per line dump with offsets and printable characters
+fn hexdump(data: &[u8]) -> String {
+    let lines: Vec<_> = data
+        .chunks(16)
+        .enumerate()
+        .map(|(i, line)| {
+            let hex: Vec<_> = line.iter().map(|b| format!("{:02x}", b)).collect();
+            let text: String = line.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }).collect();
+            format!("{:08x}  {:<47}  {}", i * 16, hex.join(" "), text)
+        })
+        .collect();
+    lines.join("\n")
+}
+
+impl Shell {
+    pub fn open(path: &str) -> Result<Shell> {
+        let saved = fs::read(path)?;
+        let png = Png::try_from(&saved[..])?;
+        Ok(Shell { path: path.to_string(), png, history: Vec::new(), saved, recorder: None, unsaved: false, quit_warned: false })
+    }
+
+    /// records every `save` with `recorder`
+    pub fn record_history(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+    }
+
+    /// index of an existing chunk
+    fn index(&self, idx: Option<&str>) -> Result<usize> {
+        let idx: usize = idx.ok_or_else(|| usage("missing chunk index"))?.parse().map_err(|_| usage("chunk index is not a number"))?;
+        if idx >= self.png.chunks().len() {
+            return Err(usage("chunk index out of range"));
+        }
+        Ok(idx)
+    }
+
+    fn chunk(&self, idx: Option<&str>) -> Result<&Chunk> {
+        Ok(&self.png.chunks()[self.index(idx)?])
+    }
+
+    /// remembers the file as it is before an edit
+    fn checkpoint(&mut self) {
+        self.history.push(self.png.as_bytes());
+        self.unsaved = true;
+    }
+
+    /// runs one command line and returns what to print, `None` once the shell should close
+    pub fn execute(&mut self, line: &str) -> Result<Option<String>> {
+        let line = line.trim();
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let mut args = rest.split_whitespace();
+        if command != "quit" && command != "exit" {
+            self.quit_warned = false;
+        }
+
+        let out = match command {
+            "" => String::new(),
+            "help" => HELP.to_string(),
+            "ls" => {
+                let lines: Vec<_> = self.png.chunks().iter().enumerate().map(|(i, ch)| format!("{:>3}  {}", i, ch)).collect();
+                lines.join("\n")
+            }
+            "cat" => String::from_utf8_lossy(self.chunk(args.next())?.data()).replace('\0', " "),
+            "hexdump" => hexdump(self.chunk(args.next())?.data()),
+            "rm" => {
+                let idx = self.index(args.next())?;
+                self.checkpoint();
+                format!("removed {}", self.png.remove_chunk_at(idx)?.chunk_type())
+            }
+            "add" => {
+                let (chunk_type, text) = rest.trim_start().split_once(char::is_whitespace).ok_or_else(|| usage("usage: add <type> <text>"))?;
+                let chunk_type = ChunkType::from_str(chunk_type)?;
+                if !chunk_type.is_valid() {
+                    return Err(usage("chunk type has the reserved bit set"));
+                }
+                self.checkpoint();
+                let idx = self.png.insert_before_end(Chunk::new(chunk_type, text.trim().as_bytes().to_vec()));
+                format!("added chunk {}", idx)
+            }
+            "mv" => {
+                let (from, to) = (self.index(args.next())?, self.index(args.next())?);
+                self.checkpoint();
+                let chunk = self.png.remove_chunk_at(from)?;
+                self.png.insert_chunk(to, chunk);
+                format!("moved chunk {} to {}", from, to)
+            }
+            "validate" => {
+                // loading recomputes every crc, so they are checked in the file as read or last saved
+                let loaded = PngRef::try_from(&self.saved[..])?;
+                if loaded.chunks().iter().any(|ch| !ch.is_crc_valid()) {
+                    return Err(usage("invalid, a chunk crc does not match its data in the file"));
+                }
+                commands::validate_bytes(&self.png.as_bytes()).map_err(|e| usage(&format!("invalid, {}", e)))?;
+                String::from("valid")
+            }
+            "undo" => {
+                let previous = self.history.pop().ok_or_else(|| usage("nothing to undo"))?;
+                self.png = Png::try_from(&previous[..])?;
+                self.unsaved = true;
+                String::from("undone")
+            }
+            "save" => {
+                let path = args.next().unwrap_or(&self.path).to_string();
+                if let Some(recorder) = &self.recorder {
+                    recorder.record_in(Some(&Png::try_from(&self.saved[..])?), &mut self.png, "shell")?;
+                }
+                self.saved = self.png.as_bytes();
+                fs::write(&path, &self.saved)?;
+                self.unsaved = false;
+                format!("saved to {}", path)
+            }
+            "quit" | "exit" => {
+                if self.unsaved && !self.quit_warned {
+                    self.quit_warned = true;
+                    return Ok(Some(String::from("unsaved edits, quit again to drop them")));
+                }
+                return Ok(None);
+            }
+            _ => return Err(usage("unknown command, try help")),
+        };
+
+        Ok(Some(out))
+    }
+}
+
+/// reads commands from `input` until `quit` or the end of the input,
+/// printing a prompt, the results and the errors to `output`
+pub fn run(path: &str, recorder: Option<&Recorder>, input: impl BufRead, mut output: impl Write) -> Result<()> {
+    let mut shell = Shell::open(path)?;
+    if let Some(recorder) = recorder {
+        shell.record_history(recorder.clone());
+    }
+    write!(output, "pngme> ")?;
+    output.flush()?;
+    for line in input.lines() {
+        match shell.execute(&line?) {
+            Ok(None) => return Ok(()),
+            Ok(Some(out)) if out.is_empty() => {}
+            Ok(Some(out)) => writeln!(output, "{}", out)?,
+            Err(e) => writeln!(output, "{}", e)?,
+        }
+        write!(output, "pngme> ")?;
+        output.flush()?;
+    }
+    writeln!(output)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::{ColorType, Image};
+
+    fn sample_file(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("pngme-shell-{}-{}.png", name, std::process::id()));
+        let png = Png::from_image(&Image::solid(2, 2, [1, 2, 3, 255], ColorType::Rgb).unwrap()).unwrap();
+        fs::write(&path, png.as_bytes()).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn types(shell: &Shell) -> Vec<String> {
+        shell.png.chunks().iter().map(|ch| ch.chunk_type().to_string()).collect()
+    }
+
+    #[test]
+    fn test_edit_and_undo() {
+        let path = sample_file("edit");
+        let mut shell = Shell::open(&path).unwrap();
+        assert_eq!(types(&shell), ["IHDR", "IDAT", "IEND"]);
+
+        shell.execute("add ruSt hello there").unwrap();
+        assert_eq!(types(&shell), ["IHDR", "IDAT", "ruSt", "IEND"]);
+        assert_eq!(shell.execute("cat 2").unwrap().unwrap(), "hello there");
+        shell.execute("mv 2 1").unwrap();
+        assert_eq!(types(&shell), ["IHDR", "ruSt", "IDAT", "IEND"]);
+        shell.execute("rm 1").unwrap();
+        assert_eq!(types(&shell), ["IHDR", "IDAT", "IEND"]);
+
+        shell.execute("undo").unwrap();
+        shell.execute("undo").unwrap();
+        assert_eq!(types(&shell), ["IHDR", "IDAT", "ruSt", "IEND"]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_edits_stay_in_memory_until_saved() {
+        let path = sample_file("save");
+        let before = fs::read(&path).unwrap();
+        let mut shell = Shell::open(&path).unwrap();
+        shell.execute("add teXt note").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), before);
+
+        assert!(shell.execute("quit").unwrap().is_some());
+        shell.execute("save").unwrap();
+        assert!(Png::try_from(&fs::read(&path).unwrap()[..]).unwrap().chunk_by_type("teXt").is_some());
+        assert!(shell.execute("quit").unwrap().is_none());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_validate_and_errors() {
+        let path = sample_file("validate");
+        let mut shell = Shell::open(&path).unwrap();
+        assert_eq!(shell.execute("validate").unwrap().unwrap(), "valid");
+        shell.execute("mv 0 2").unwrap();
+        assert!(shell.execute("validate").is_err());
+
+        assert!(shell.execute("rm 9").is_err());
+        assert!(shell.execute("cat x").is_err());
+        assert!(shell.execute("add ruSt").is_err());
+        assert!(shell.execute("frobnicate").is_err());
+        assert!(Shell::open(&path).unwrap().execute("undo").is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_validate_checks_crcs_as_read() {
+        let path = sample_file("crc");
+        let mut bytes = fs::read(&path).unwrap();
+        // last byte of the IHDR crc
+        bytes[8 + 8 + 13 + 3] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+
+        let mut output = Vec::new();
+        run(&path, None, &b"validate\n"[..], &mut output).unwrap();
+        assert!(String::from_utf8(output).unwrap().contains("invalid"));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_run_and_hexdump() {
+        let path = sample_file("run");
+        let mut output = Vec::new();
+        run(&path, None, &b"hexdump 0\nbogus\nquit\n"[..], &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("00000000  00 00 00 02 00 00 00 02 08 02 00 00 00"));
+        assert!(output.contains("unknown command"));
+        fs::remove_file(path).unwrap();
+    }
+}
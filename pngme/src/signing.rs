@@ -0,0 +1,113 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use ed25519_dalek::{SigningKey, VerifyingKey};
+
+use crate::png::PngError;
+use crate::{Error, Result};
+
+// Keys are stored as a single line of hex: 32 secret bytes in `.key` files and
+// the 32 bytes of the public key in `.pub` files.
+
+fn key_error(msg: &'static str) -> Error {
+    Box::new(PngError { msg })
+}
+
+fn read_key_bytes(path: &Path) -> Result<[u8; 32]> {
+    let text = fs::read_to_string(path)?;
+    let bytes = hex::decode(text.trim())?;
+    bytes.try_into().map_err(|_| key_error("keys must be 32 bytes long"))
+}
+
+pub fn generate_key() -> Result<SigningKey> {
+    let mut secret = [0; 32];
+    getrandom::getrandom(&mut secret)?;
+    Ok(SigningKey::from_bytes(&secret))
+}
+
+pub fn read_signing_key(path: &Path) -> Result<SigningKey> {
+    Ok(SigningKey::from_bytes(&read_key_bytes(path)?))
+}
+
+pub fn read_verifying_key(path: &Path) -> Result<VerifyingKey> {
+    Ok(VerifyingKey::from_bytes(&read_key_bytes(path)?)?)
+}
+
+/// creates `path` holding `bytes` as hex, failing if it exists; on unix the
+/// file gets `mode` from the start so a secret is never readable by others
+fn create_key_file(path: &str, bytes: &[u8], mode: u32) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, mode);
+    #[cfg(not(unix))]
+    let _ = mode;
+
+    let mut file = options.open(path).map_err(|e| match e.kind() {
+        std::io::ErrorKind::AlreadyExists => key_error("key files already exist"),
+        _ => e.into(),
+    })?;
+    file.write_all((hex::encode(bytes) + "\n").as_bytes())?;
+    Ok(())
+}
+
+/// writes `<prefix>.key`, readable by its owner only, and `<prefix>.pub`,
+/// never overwriting existing files
+pub fn write_key_pair(key: &SigningKey, prefix: &str) -> Result<(String, String)> {
+    let secret_path = format!("{}.key", prefix);
+    let public_path = format!("{}.pub", prefix);
+
+    create_key_file(&secret_path, &key.to_bytes(), 0o600)?;
+    if let Err(e) = create_key_file(&public_path, &key.verifying_key().to_bytes(), 0o644) {
+        fs::remove_file(&secret_path)?;
+        return Err(e);
+    }
+    Ok((secret_path, public_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_pair_round_trip() {
+        let prefix = std::env::temp_dir()
+            .join(format!("pngme-keys-{}", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        let key = generate_key().unwrap();
+
+        let (secret_path, public_path) = write_key_pair(&key, &prefix).unwrap();
+        assert!(write_key_pair(&key, &prefix).is_err());
+        assert_eq!(read_signing_key(Path::new(&secret_path)).unwrap().to_bytes(), key.to_bytes());
+        assert_eq!(read_verifying_key(Path::new(&public_path)).unwrap(), key.verifying_key());
+
+        fs::remove_file(secret_path).unwrap();
+        fs::remove_file(public_path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_secret_key_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let prefix = std::env::temp_dir()
+            .join(format!("pngme-keys-mode-{}", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        let (secret_path, public_path) = write_key_pair(&generate_key().unwrap(), &prefix).unwrap();
+        assert_eq!(fs::metadata(&secret_path).unwrap().permissions().mode() & 0o777, 0o600);
+
+        // an existing public key leaves no secret key behind
+        fs::remove_file(&secret_path).unwrap();
+        assert!(write_key_pair(&generate_key().unwrap(), &prefix).is_err());
+        assert!(!Path::new(&secret_path).exists());
+        fs::remove_file(public_path).unwrap();
+    }
+
+    #[test]
+    fn test_generated_keys_differ() {
+        assert_ne!(generate_key().unwrap().to_bytes(), generate_key().unwrap().to_bytes());
+    }
+}
@@ -0,0 +1,120 @@
+use std::fmt::Write;
+
+use crate::image::{ColorType, Image};
+use crate::png::PngError;
+use crate::Result;
+
+/// color shown behind transparent pixels
+const BACKGROUND: [u8; 3] = [32, 32, 32];
+const MARK: [u8; 3] = [255, 0, 64];
+
+/// what `render` draws on top of the image
+pub enum Overlay {
+    None,
+    /// shows only the least significant bit of every channel, where LSB
+    /// embedding turns smooth areas into noise
+    LsbPlane,
+    /// marks the pixels that differ from the given image, dimming the others
+    Diff(Image),
+}
+
+fn blend(rgba: &[u8]) -> [u8; 3] {
+    let alpha = rgba[3] as u32;
+    let mix = |c: u8, bg: u8| ((c as u32 * alpha + bg as u32 * (255 - alpha)) / 255) as u8;
+    [mix(rgba[0], BACKGROUND[0]), mix(rgba[1], BACKGROUND[1]), mix(rgba[2], BACKGROUND[2])]
+}
+
+/// colors of every pixel and whether it is marked
+fn prepare(image: &Image, overlay: &Overlay) -> Result<Vec<([u8; 3], bool)>> {
+    let pixels = image.data.chunks_exact(4);
+    Ok(match overlay {
+        Overlay::None => pixels.map(|px| (blend(px), false)).collect(),
+        Overlay::LsbPlane => pixels.map(|px| ([px[0] & 1, px[1] & 1, px[2] & 1].map(|bit| bit * 255), false)).collect(),
+        Overlay::Diff(original) => {
+            if (original.width, original.height) != (image.width, image.height) {
+                return Err(Box::new(PngError { msg: "images to compare differ in size" }));
+            }
+            pixels
+                .zip(original.data.chunks_exact(4))
+                .map(|(px, orig)| (blend(px).map(|c| c / 3), px != orig))
+                .collect()
+        }
+    })
+}
+
+/// draws `image` with unicode half blocks and 24 bit ansi colors, at most
+/// `columns` characters wide; every character shows two pixels stacked
+pub fn render(image: &Image, columns: u32, overlay: &Overlay) -> Result<String> {
+    if image.color_type != ColorType::Rgba {
+        return Err(Box::new(PngError { msg: "only rgba images can be rendered" }));
+    }
+    let pixels = prepare(image, overlay)?;
+    let (width, height) = (image.width as usize, image.height as usize);
+    let out_width = width.min(columns.max(1) as usize);
+    let out_height = (height * out_width).div_ceil(width).max(1);
+
+    // averages the box of source pixels behind an output pixel, marked boxes win
+    let sample = |ox: usize, oy: usize| -> [u8; 3] {
+        let (x0, x1) = (ox * width / out_width, ((ox + 1) * width / out_width).max(ox * width / out_width + 1));
+        let (y0, y1) = (oy * height / out_height, ((oy + 1) * height / out_height).max(oy * height / out_height + 1));
+        let mut sum = [0u32; 3];
+        for y in y0..y1 {
+            for (color, marked) in &pixels[y * width + x0..y * width + x1] {
+                if *marked {
+                    return MARK;
+                }
+                sum.iter_mut().zip(color).for_each(|(s, &c)| *s += c as u32);
+            }
+        }
+        let count = ((x1 - x0) * (y1 - y0)) as u32;
+        sum.map(|s| (s / count) as u8)
+    };
+
+    let mut out = String::new();
+    for row in (0..out_height).step_by(2) {
+        for col in 0..out_width {
+            let [r, g, b] = sample(col, row);
+            let [br, bg, bb] = if row + 1 < out_height { sample(col, row + 1) } else { BACKGROUND };
+            write!(out, "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m\u{2580}", r, g, b, br, bg, bb)?;
+        }
+        out.push_str("\x1b[0m\n");
+    }
+    out.pop();
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: u32, height: u32, rgba: [u8; 4]) -> Image {
+        Image::solid(width, height, rgba, ColorType::Rgba).unwrap()
+    }
+
+    #[test]
+    fn test_render_downscales() {
+        let out = render(&image(100, 50, [1, 2, 3, 255]), 20, &Overlay::None).unwrap();
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines.iter().all(|line| line.matches('\u{2580}').count() == 20));
+        assert!(out.contains("\x1b[38;2;1;2;3m"));
+    }
+
+    #[test]
+    fn test_render_marks_changed_pixels() {
+        let original = image(4, 4, [100, 100, 100, 255]);
+        let mut changed = original.clone();
+        changed.data[0] ^= 1;
+
+        let out = render(&changed, 4, &Overlay::Diff(original.clone())).unwrap();
+        assert_eq!(out.matches("\x1b[38;2;255;0;64m").count(), 1);
+        assert!(render(&changed, 4, &Overlay::Diff(image(2, 2, [0; 4]))).is_err());
+    }
+
+    #[test]
+    fn test_render_lsb_plane() {
+        let out = render(&image(2, 2, [3, 2, 1, 255]), 2, &Overlay::LsbPlane).unwrap();
+        assert!(out.starts_with("\x1b[38;2;255;0;255m\x1b[48;2;255;0;255m"));
+    }
+}
@@ -0,0 +1,280 @@
+use std::fmt;
+
+use crc::{Crc, CRC_16_IBM_SDLC};
+
+use crate::image::{ColorType, Image};
+use crate::lsb::KeyStream;
+use crate::png::PngError;
+use crate::{Error, Result};
+
+// The mark is a sum of keyed pseudo-random +1/-1 patterns over a 64x64 tile,
+// one for synchronisation and one per bit of the id and its checksum, each
+// added with the sign of its bit. The tile repeats over the whole image and
+// is added to every color sample, so it lives in the pixels rather than in
+// a chunk. Detection high-pass filters the luminance, folds it onto a single
+// tile and looks for the cyclic shift where the sync pattern correlates best;
+// a crop only moves that shift, and noise from requantisation averages out
+// over the repeated tiles.
+
+const TILE: usize = 64;
+const ID_BITS: usize = 32;
+const CHECK_BITS: usize = 16;
+/// the sync pattern and one pattern per bit of the id and its checksum
+const PATTERNS: usize = 1 + ID_BITS + CHECK_BITS;
+/// standard deviation of the mark in 8 bit levels when no strength is given
+pub const DEFAULT_STRENGTH: f32 = 2.0;
+
+const CHECKSUM: Crc<u16> = Crc::<u16>::new(&CRC_16_IBM_SDLC);
+
+fn watermark_error(msg: &'static str) -> Error {
+    Box::new(PngError { msg })
+}
+
+/// what `detect` found in an image
+#[derive(Debug, Clone, PartialEq)]
+pub struct Detection {
+    /// `None` when a mark was found but its checksum does not match
+    pub id: Option<u32>,
+    /// probability that the sync peak is not a chance match, between 0 and 1;
+    /// unmarked images stay below 0.999 all but once in a thousand
+    pub confidence: f64,
+    /// position of the image origin in the tile, moved by crops
+    pub offset: (u32, u32),
+}
+
+impl fmt::Display for Detection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.id {
+            Some(id) => write!(f, "watermark id {}", id)?,
+            None => write!(f, "no readable watermark id")?,
+        }
+        write!(f, " (confidence {:.3})", self.confidence)
+    }
+}
+
+/// the keyed +1/-1 patterns, sync pattern first, one tile each
+fn patterns(key: &[u8]) -> Vec<Vec<f32>> {
+    let mut stream = KeyStream::new(&[b"pngme watermark\0", key].concat());
+    (0..PATTERNS)
+        .map(|_| {
+            let mut pattern = Vec::with_capacity(TILE * TILE);
+            while pattern.len() < TILE * TILE {
+                let word = stream.next_u64();
+                pattern.extend((0..64).map(|i| if word >> i & 1 == 1 { 1.0 } else { -1.0 }));
+            }
+            pattern
+        })
+        .collect()
+}
+
+/// sign of every pattern: +1 for sync, then the id and checksum bits
+fn signs(id: u32) -> Vec<f32> {
+    let checksum = CHECKSUM.checksum(&id.to_be_bytes());
+    let bits = (0..ID_BITS).rev().map(|i| id >> i & 1).chain((0..CHECK_BITS).rev().map(|i| (checksum >> i & 1) as u32));
+    std::iter::once(1.0).chain(bits.map(|bit| if bit == 1 { 1.0 } else { -1.0 })).collect()
+}
+
+/// adds the mark for `id` to every color sample; `strength` is the standard
+/// deviation of the mark in 8 bit levels
+pub fn embed(image: &mut Image, id: u32, key: &[u8], strength: f32) -> Result<()> {
+    if image.color_type == ColorType::Indexed || image.bit_depth < 8 {
+        return Err(watermark_error("watermarks need an 8 or 16 bit image that is not indexed"));
+    }
+    if !(strength.is_finite() && strength > 0.0) {
+        return Err(watermark_error("watermark strength must be positive"));
+    }
+    let scale = strength / (PATTERNS as f32).sqrt() * if image.bit_depth == 16 { 257.0 } else { 1.0 };
+    let mut tile = vec![0.0f32; TILE * TILE];
+    for (pattern, sign) in patterns(key).iter().zip(signs(id)) {
+        tile.iter_mut().zip(pattern).for_each(|(t, p)| *t += sign * p * scale);
+    }
+
+    let channels = image.color_type.channels();
+    let colors = match image.color_type {
+        ColorType::GrayAlpha | ColorType::Rgba => channels - 1,
+        _ => channels,
+    };
+    let (width, bytes) = (image.width as usize, image.bit_depth as usize / 8);
+    for (px, pixel) in image.data.chunks_exact_mut(channels * bytes).enumerate() {
+        let delta = tile[(px / width % TILE) * TILE + px % width % TILE];
+        for sample in pixel[..colors * bytes].chunks_exact_mut(bytes) {
+            match bytes {
+                2 => {
+                    let value = u16::from_be_bytes([sample[0], sample[1]]) as f32 + delta;
+                    sample.copy_from_slice(&(value.round().clamp(0.0, 65535.0) as u16).to_be_bytes());
+                }
+                _ => sample[0] = (sample[0] as f32 + delta).round().clamp(0.0, 255.0) as u8,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// luminance of every pixel, whatever the color type
+fn luminance(image: &Image) -> Result<Vec<f32>> {
+    let rgba = image.to_rgba(None)?;
+    Ok(rgba.data.chunks_exact(4).map(|px| 0.299 * px[0] as f32 + 0.587 * px[1] as f32 + 0.114 * px[2] as f32).collect())
+}
+
+/// laplacian of the luminance folded onto one tile, which removes most of
+/// the picture and keeps the white noise of the mark
+fn fold(image: &Image) -> Result<Vec<f32>> {
+    let (width, height) = (image.width as usize, image.height as usize);
+    if width < TILE || height < TILE {
+        return Err(watermark_error("image is smaller than a watermark tile"));
+    }
+    let lum = luminance(image)?;
+    let mut folded = vec![0.0f32; TILE * TILE];
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let i = y * width + x;
+            let laplacian = 4.0 * lum[i] - lum[i - 1] - lum[i + 1] - lum[i - width] - lum[i + width];
+            folded[(y % TILE) * TILE + x % TILE] += laplacian;
+        }
+    }
+
+    Ok(folded)
+}
+
+/// `pattern` repeated over two tiles in each direction, so that any cyclic
+/// shift of it is a plain window
+fn doubled(pattern: &[f32]) -> Vec<f32> {
+    (0..2 * TILE).flat_map(|y| (0..2 * TILE).map(move |x| pattern[(y % TILE) * TILE + x % TILE])).collect()
+}
+
+/// correlation of the folded tile with a doubled pattern moved by (dx, dy)
+fn correlate(folded: &[f32], doubled: &[f32], dx: usize, dy: usize) -> f64 {
+    let mut sum = 0.0;
+    for (y, folded) in folded.chunks_exact(TILE).enumerate() {
+        let window = &doubled[(y + dy) * 2 * TILE + dx..][..TILE];
+        sum += folded.iter().zip(window).map(|(f, p)| f * p).sum::<f32>() as f64;
+    }
+    sum
+}
+
+/// upper tail of the standard normal distribution, after Abramowitz and
+/// Stegun 7.1.26
+fn normal_tail(z: f64) -> f64 {
+    if z < 0.0 {
+        return 1.0 - normal_tail(-z);
+    }
+    let x = z / std::f64::consts::SQRT_2;
+    let t = 1.0 / (1.0 + 0.327_591_1 * x);
+    let poly = t * (0.254_829_592 + t * (-0.284_496_736 + t * (1.421_413_741 + t * (-1.453_152_027 + t * 1.061_405_429))));
+    0.5 * poly * (-x * x).exp()
+}
+
+/// looks for a mark made with `key` and reads its id
+pub fn detect(image: &Image, key: &[u8]) -> Result<Detection> {
+    let folded = fold(image)?;
+    let patterns: Vec<_> = patterns(key).iter().map(|pattern| doubled(pattern)).collect();
+
+    let shifts: Vec<f64> = (0..TILE * TILE).map(|s| correlate(&folded, &patterns[0], s % TILE, s / TILE)).collect();
+    let (best, peak) = shifts.iter().enumerate().fold((0, f64::MIN), |best, (s, &c)| if c > best.1 { (s, c) } else { best });
+    let mean = shifts.iter().sum::<f64>() / shifts.len() as f64;
+    let deviation = (shifts.iter().map(|c| (c - mean).powi(2)).sum::<f64>() / shifts.len() as f64).sqrt();
+    // the peak is the best of every shift, so a chance match is that many times more likely
+    let z = if deviation > 0.0 { (peak - mean) / deviation } else { 0.0 };
+    let confidence = 1.0 - (normal_tail(z) * shifts.len() as f64).min(1.0);
+
+    let (dx, dy) = (best % TILE, best / TILE);
+    let bits = patterns[1..].iter().fold(0u64, |acc, pattern| acc << 1 | (correlate(&folded, pattern, dx, dy) > 0.0) as u64);
+    let (id, checksum) = ((bits >> CHECK_BITS) as u32, (bits & 0xffff) as u16);
+    let id = (CHECKSUM.checksum(&id.to_be_bytes()) == checksum).then_some(id);
+
+    Ok(Detection { id, confidence, offset: (dx as u32, dy as u32) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// smooth gradient with texture, like a photograph
+    fn photo(width: u32, height: u32) -> Image {
+        let mut state = 99u32;
+        let rgba: Vec<u8> = (0..width * height)
+            .flat_map(|i| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                let noise = (state >> 16) % 9;
+                let (x, y) = (i % width, i / width);
+                let v = ((x * 3 + y * 2) * 200 / (width * 3 + height * 2)) + noise + 20;
+                [v as u8, (v / 2) as u8, (240 - v) as u8, 255]
+            })
+            .collect();
+        Image::from_rgba(width, height, &rgba, ColorType::Rgba).unwrap()
+    }
+
+    fn crop(image: &Image, left: u32, top: u32, width: u32, height: u32) -> Image {
+        let rgba = image.to_rgba(None).unwrap();
+        let data: Vec<u8> = (top..top + height)
+            .flat_map(|y| {
+                let start = (y * image.width + left) as usize * 4;
+                rgba.data[start..start + width as usize * 4].to_vec()
+            })
+            .collect();
+        Image::from_rgba(width, height, &data, ColorType::Rgba).unwrap()
+    }
+
+    /// png written and read back by another encoder, which keeps no chunks of ours
+    fn reencode(image: &Image) -> Image {
+        let rgba = image.to_rgba(None).unwrap();
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, image.width, image.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_compression(png::Compression::Best);
+        encoder.write_header().unwrap().write_image_data(&rgba.data).unwrap();
+
+        let mut reader = png::Decoder::new(&bytes[..]).read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut data).unwrap();
+        Image::from_rgba(image.width, image.height, &data, ColorType::Rgba).unwrap()
+    }
+
+    #[test]
+    fn test_survives_reencoding_crop_and_requantisation() {
+        let mut image = photo(256, 256);
+        embed(&mut image, 0xdead_beef, b"key", DEFAULT_STRENGTH).unwrap();
+
+        let mut leaked = crop(&reencode(&image), 13, 7, 220, 230);
+        // drops the lowest bit of every sample, like a lossy optimiser would
+        leaked.data.iter_mut().for_each(|v| *v &= !1);
+
+        let detection = detect(&leaked, b"key").unwrap();
+        assert_eq!(detection.id, Some(0xdead_beef));
+        assert!(detection.confidence > 0.999);
+        assert_eq!(detection.offset, (13, 7));
+    }
+
+    #[test]
+    fn test_unmarked_or_other_key() {
+        let mut image = photo(128, 128);
+        let detection = detect(&image, b"key").unwrap();
+        assert!(detection.confidence < 0.99);
+
+        embed(&mut image, 42, b"key", DEFAULT_STRENGTH).unwrap();
+        assert_eq!(detect(&image, b"key").unwrap().id, Some(42));
+        assert!(detect(&image, b"other").unwrap().confidence < 0.99);
+    }
+
+    #[test]
+    fn test_mark_is_faint() {
+        let original = photo(128, 128);
+        let mut marked = original.clone();
+        embed(&mut marked, 7, b"key", DEFAULT_STRENGTH).unwrap();
+
+        let diffs: Vec<f64> = original.data.iter().zip(&marked.data).map(|(&a, &b)| a as f64 - b as f64).collect();
+        let mse = diffs.iter().map(|d| d * d).sum::<f64>() / diffs.len() as f64;
+        assert!(10.0 * (255.0f64 * 255.0 / mse).log10() > 40.0);
+        // alpha is left alone
+        assert!(original.data.chunks(4).zip(marked.data.chunks(4)).all(|(a, b)| a[3] == b[3]));
+    }
+
+    #[test]
+    fn test_rejects_unusable_images() {
+        let mut indexed = Image::solid(64, 64, [1, 2, 3, 255], ColorType::Indexed).unwrap();
+        assert!(embed(&mut indexed, 1, b"key", DEFAULT_STRENGTH).is_err());
+        assert!(detect(&photo(32, 128), b"key").is_err());
+    }
+}
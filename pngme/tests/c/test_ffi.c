@@ -0,0 +1,77 @@
+/* exercises the c api against the png given as the only argument */
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "pngme.h"
+
+#define CHECK(cond)                                                        \
+    do {                                                                   \
+        if (!(cond)) {                                                     \
+            fprintf(stderr, "%s:%d: %s failed: %s\n", __FILE__, __LINE__,  \
+                    #cond, pngme_last_error());                            \
+            return 1;                                                      \
+        }                                                                  \
+    } while (0)
+
+static uint8_t *read_file(const char *path, size_t *len) {
+    FILE *file = fopen(path, "rb");
+    if (!file) {
+        return NULL;
+    }
+    fseek(file, 0, SEEK_END);
+    *len = (size_t)ftell(file);
+    fseek(file, 0, SEEK_SET);
+    uint8_t *data = malloc(*len);
+    if (data && fread(data, 1, *len, file) != *len) {
+        free(data);
+        data = NULL;
+    }
+    fclose(file);
+    return data;
+}
+
+int main(int argc, char **argv) {
+    const char *message = "hello from c";
+    size_t len;
+    uint8_t *input = argc == 2 ? read_file(argv[1], &len) : NULL;
+    CHECK(input != NULL);
+
+    PngmePng *png = NULL;
+    CHECK(pngme_open(input, len, &png) == PNGME_STATUS_OK);
+    free(input);
+
+    PngmeChunk *chunks;
+    size_t count;
+    CHECK(pngme_list_chunks(png, &chunks, &count) == PNGME_STATUS_OK);
+    CHECK(count >= 3 && strcmp(chunks[0].chunk_type, "IHDR") == 0 && chunks[0].length == 13);
+    pngme_free_chunks(chunks, count);
+
+    char used_type[5];
+    CHECK(pngme_encode(png, NULL, (const uint8_t *)message, strlen(message), used_type) == PNGME_STATUS_OK);
+    CHECK(strcmp(used_type, "pmAa") == 0);
+    CHECK(pngme_encode(png, "IDAT", (const uint8_t *)"x", 1, NULL) == PNGME_STATUS_INVALID_ARGUMENT);
+    CHECK(strlen(pngme_last_error()) > 0);
+
+    uint8_t *bytes;
+    size_t bytes_len;
+    CHECK(pngme_to_bytes(png, &bytes, &bytes_len) == PNGME_STATUS_OK);
+    pngme_free(png);
+
+    PngmePng *reopened = NULL;
+    CHECK(pngme_open(bytes, bytes_len, &reopened) == PNGME_STATUS_OK);
+    pngme_free_bytes(bytes, bytes_len);
+
+    uint8_t *decoded;
+    size_t decoded_len;
+    CHECK(pngme_decode(reopened, NULL, &decoded, &decoded_len) == PNGME_STATUS_OK);
+    CHECK(decoded_len == strlen(message) && memcmp(decoded, message, decoded_len) == 0);
+    pngme_free_bytes(decoded, decoded_len);
+
+    CHECK(pngme_decode(reopened, "zzZz", &decoded, &decoded_len) == PNGME_STATUS_NOT_FOUND);
+    CHECK(pngme_open(NULL, 0, &png) == PNGME_STATUS_NULL_POINTER);
+    pngme_free(reopened);
+
+    puts("c api ok");
+    return 0;
+}
@@ -0,0 +1,59 @@
+//! checks the committed c header and builds tests/c/test_ffi.c against the cdylib
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use pngme::image::{ColorType, Image};
+use pngme::png::Png;
+
+/// target/<profile>, where cargo puts the cdylib next to the deps directory
+fn profile_dir() -> PathBuf {
+    let exe = std::env::current_exe().unwrap();
+    exe.parent().and_then(Path::parent).unwrap().to_path_buf()
+}
+
+#[test]
+fn test_header_is_up_to_date() {
+    let generated = Path::new(env!("OUT_DIR")).join("pngme.h");
+    let checked_in = Path::new(env!("CARGO_MANIFEST_DIR")).join("include/pngme.h");
+    assert!(
+        std::fs::read(&generated).unwrap() == std::fs::read(&checked_in).unwrap(),
+        "{} is out of date, copy {} over it",
+        checked_in.display(),
+        generated.display()
+    );
+}
+
+#[test]
+fn test_c_program() {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let lib_dir = profile_dir();
+    let tmp = std::env::temp_dir().join(format!("pngme-ffi-{}", std::process::id()));
+    std::fs::create_dir_all(&tmp).unwrap();
+    let (program, input) = (tmp.join("test_ffi"), tmp.join("input.png"));
+
+    let image = Image::noise(8, 8, 1, ColorType::Rgb).unwrap();
+    std::fs::write(&input, Png::from_image(&image).unwrap().as_bytes()).unwrap();
+
+    let compiled = Command::new(std::env::var("CC").unwrap_or_else(|_| String::from("cc")))
+        .arg(manifest_dir.join("tests/c/test_ffi.c"))
+        .arg("-I")
+        .arg(manifest_dir.join("include"))
+        .arg("-L")
+        .arg(&lib_dir)
+        .arg("-lpngme")
+        .arg("-o")
+        .arg(&program)
+        .status()
+        .expect("a c compiler is needed to test the c api");
+    assert!(compiled.success());
+
+    let output = Command::new(&program)
+        .arg(&input)
+        .env("LD_LIBRARY_PATH", &lib_dir)
+        .env("DYLD_LIBRARY_PATH", &lib_dir)
+        .output()
+        .unwrap();
+    std::fs::remove_dir_all(&tmp).unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "c api ok\n");
+}
@@ -0,0 +1,70 @@
+# run with `maturin develop && pytest tests/python` from the pngme directory
+import struct
+import zlib
+
+import pytest
+
+import pngme
+
+
+def chunk(chunk_type, data):
+    body = chunk_type + data
+    return struct.pack(">I", len(data)) + body + struct.pack(">I", zlib.crc32(body))
+
+
+def sample_png():
+    ihdr = struct.pack(">IIBBBBB", 1, 1, 8, 2, 0, 0, 0)
+    idat = zlib.compress(b"\x00\x10\x20\x30")
+    return b"\x89PNG\r\n\x1a\n" + chunk(b"IHDR", ihdr) + chunk(b"IDAT", idat) + chunk(b"IEND", b"")
+
+
+def test_open_and_chunks(tmp_path):
+    path = tmp_path / "sample.png"
+    path.write_bytes(sample_png())
+    png = pngme.Png.open(str(path))
+    assert [t for t, _ in png.chunks] == ["IHDR", "IDAT", "IEND"]
+    assert png.to_bytes() == sample_png()
+
+
+def test_encode_decode_round_trip():
+    png = pngme.Png(sample_png())
+    chunk_type = png.encode("provenance: run 42", ecc=8, compress="zlib")
+    reopened = pngme.Png(png.to_bytes())
+    assert reopened.decode() == b"provenance: run 42"
+    assert reopened.decode(chunk_type) == b"provenance: run 42"
+    assert png.encode(b"raw bytes", chunk_type="ruSt") == "ruSt"
+    assert png.decode("ruSt") == b"raw bytes"
+
+
+def test_remove_and_strip():
+    png = pngme.Png(sample_png())
+    png.encode("kept", chunk_type="ruSt")
+    png.encode("dropped", chunk_type="ruST")
+    png.encode("listed", chunk_type="tIME")
+    assert png.strip(keep=["tIME"]) == ["ruST"]
+    assert png.remove("ruSt")
+    assert "ruSt" not in [t for t, _ in png.chunks]
+
+
+def test_remove_clears_the_record():
+    png = pngme.Png(sample_png())
+    chunk_type = png.encode("picked for me")
+    assert "tEXt" in [t for t, _ in png.chunks]
+    assert png.remove(chunk_type) == b"picked for me"
+    assert png.to_bytes() == sample_png()
+    with pytest.raises(pngme.PngError):
+        png.decode()
+
+
+def test_errors_match_rust_errors(tmp_path):
+    with pytest.raises(pngme.PngError):
+        pngme.Png(b"not a png")
+    with pytest.raises(pngme.PngError):
+        pngme.Png(sample_png()).remove("teXt")
+    with pytest.raises(pngme.ChunkTypeError):
+        pngme.Png(sample_png()).encode("x", chunk_type="r1St")
+    with pytest.raises(pngme.PayloadError):
+        pngme.Png(sample_png()).encode("x", compress="lzma")
+    with pytest.raises(OSError):
+        pngme.Png.open(str(tmp_path / "missing.png"))
+    assert issubclass(pngme.CarrierError, pngme.PngmeError)
@@ -0,0 +1,130 @@
+//! randomized round trips of the parser, the serializer and the file commands
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+use pngme::chunk::Chunk;
+use pngme::chunk_type::ChunkType;
+use pngme::commands::{self, Destination, Mode};
+use pngme::image::{ColorType, Image};
+use pngme::payload::{Compression, Options};
+use pngme::png::Png;
+
+const SKELETON: [&str; 4] = ["IHDR", "PLTE", "IDAT", "IEND"];
+
+/// any type `ChunkType::is_valid` accepts, besides those of the skeleton
+fn chunk_type() -> impl Strategy<Value = ChunkType> {
+    ("[a-zA-Z]", "[a-zA-Z]", "[A-Z]", "[a-zA-Z]")
+        .prop_map(|(a, b, c, d)| ChunkType::from_str(&format!("{}{}{}{}", a, b, c, d)).unwrap())
+        .prop_filter("skeleton chunk types", |ct| !SKELETON.contains(&ct.to_string().as_str()))
+}
+
+fn ancillary_type() -> impl Strategy<Value = ChunkType> {
+    chunk_type().prop_filter("ancillary chunk types", |ct| !ct.is_critical())
+}
+
+fn color_type() -> impl Strategy<Value = ColorType> {
+    prop_oneof![
+        Just(ColorType::Gray),
+        Just(ColorType::Rgb),
+        Just(ColorType::Indexed),
+        Just(ColorType::GrayAlpha),
+        Just(ColorType::Rgba),
+    ]
+}
+
+/// a real IHDR/IDAT/IEND skeleton with random pixels, the image data split
+/// over several IDAT chunks and random chunks after IHDR, before IEND and
+/// after IEND
+fn arb_png() -> impl Strategy<Value = Png> {
+    let extras = vec((0..3usize, chunk_type(), vec(any::<u8>(), 0..256)), 0..8);
+    (1..=16u32, 1..=16u32, any::<u64>(), color_type(), 1..512usize, extras).prop_map(
+        |(width, height, seed, color_type, idat_size, extras)| {
+            let mut png = Png::from_image(&Image::noise(width, height, seed, color_type).unwrap()).unwrap();
+            png.set_image_data_split(png.image_data(), idat_size).unwrap();
+            for (place, chunk_type, data) in extras {
+                let index = match place {
+                    0 => 1,
+                    1 => png.chunks().len() - 1,
+                    _ => png.chunks().len(),
+                };
+                png.insert_chunk(index, Chunk::new(chunk_type, data));
+            }
+            png
+        },
+    )
+}
+
+fn options() -> impl Strategy<Value = Options> {
+    let compress = prop_oneof![Just(None), Just(Some(Compression::Zlib)), Just(Some(Compression::Zstd))];
+    (proptest::option::of(1..64u8), compress).prop_map(|(ecc, compress)| Options { ecc, sign: None, compress })
+}
+
+fn types(png: &Png) -> Vec<String> {
+    png.chunks().iter().map(|ch| ch.chunk_type().to_string()).collect()
+}
+
+fn iend(types: &[String]) -> usize {
+    types.iter().position(|t| t == "IEND").unwrap()
+}
+
+/// a file of its own for every case, the cases of a test run in parallel with other tests
+fn temp_file(png: &Png) -> String {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let name = format!("pngme-roundtrip-{}-{}.png", std::process::id(), COUNTER.fetch_add(1, Ordering::Relaxed));
+    let path = std::env::temp_dir().join(name);
+    std::fs::write(&path, png.as_bytes()).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+proptest! {
+    #[test]
+    fn test_png_round_trip(png in arb_png()) {
+        let bytes = png.as_bytes();
+        let parsed = Png::try_from(&bytes[..]).unwrap();
+        prop_assert_eq!(&parsed, &png);
+        prop_assert_eq!(parsed.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_chunk_round_trip(chunk_type in chunk_type(), data in vec(any::<u8>(), 0..1024)) {
+        let chunk = Chunk::new(chunk_type, data);
+        prop_assert_eq!(Chunk::try_from(&chunk.as_bytes()[..]).unwrap(), chunk);
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn test_encode_then_decode(png in arb_png(), message in "\\PC{0,300}", explicit in proptest::option::of(ancillary_type()), options in options()) {
+        let explicit = explicit.map(|ct| ct.to_string()).filter(|ct| png.chunk_by_type(ct).is_none());
+        let path = temp_file(&png);
+        commands::encode(path.clone(), Mode::Chunk, explicit.clone(), message.clone(), None, Destination::InPlace, &options).unwrap();
+        let payload = commands::decode(path.clone(), Mode::Chunk, explicit, None, None).unwrap();
+        let written = Png::try_from(&std::fs::read(&path).unwrap()[..]).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        prop_assert_eq!(payload.message, message.into_bytes());
+
+        // the new chunks go before IEND, whatever follows it stays as it was
+        let (before, after) = (types(&png), types(&written));
+        let (end, written_end) = (iend(&before), iend(&after));
+        prop_assert!(written_end > end);
+        prop_assert_eq!(&after[..end], &before[..end]);
+        prop_assert_eq!(&after[written_end..], &before[end..]);
+    }
+
+    #[test]
+    fn test_remove_after_encode_restores_bytes(png in arb_png(), message in "\\PC{0,300}", explicit in proptest::option::of(ancillary_type()), options in options()) {
+        let explicit = explicit.map(|ct| ct.to_string()).filter(|ct| png.chunk_by_type(ct).is_none());
+        let path = temp_file(&png);
+        let location = commands::encode(path.clone(), Mode::Chunk, explicit, message, None, Destination::InPlace, &options).unwrap();
+        let chunk_type = location.trim_start_matches("chunk ").to_string();
+        commands::remove(path.clone(), chunk_type).unwrap();
+        let restored = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        prop_assert_eq!(restored, png.as_bytes());
+    }
+}